@@ -1,6 +1,7 @@
 use crate::conf::{Action, ActionType};
 use crate::error::NgsLogActionError;
-use crate::ngs_log::NgsLog;
+use crate::ngs_log::{ItemLog, NgsLog};
+use crate::template::Variables;
 use crate::{format_datetime, now, CONF};
 use anyhow::Result;
 use chrono::{DateTime, FixedOffset};
@@ -10,9 +11,9 @@ use once_cell::sync::Lazy;
 use std::cmp::{max, Ordering};
 use std::collections::HashMap;
 use std::ops;
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
 use std::{io::Write, process::Command};
 use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
-use tokio::spawn;
 use tokio::sync::Mutex;
 use unicode_width::UnicodeWidthStr;
 
@@ -39,49 +40,218 @@ impl ops::AddAssign<u32> for Counter {
 pub static ITEM_COUNTER: Lazy<Mutex<HashMap<String, Counter>>> =
  Lazy::new(|| Mutex::new(HashMap::new()));
 static ITEM_COUNTER_BEGIN: Lazy<Mutex<DateTime<FixedOffset>>> = Lazy::new(|| Mutex::new(now()));
+pub static MESETA_TRACKER: Lazy<Mutex<MesetaTracker>> =
+ Lazy::new(|| Mutex::new(MesetaTracker::default()));
+/// リプレイ中は最後に再生したログの日時（リプレイ中でなければ None ）
+static REPLAY_TIME: Lazy<Mutex<Option<DateTime<FixedOffset>>>> = Lazy::new(|| Mutex::new(None));
+
+/// 再生するログの日時を集計の現在時刻にします。最初に再生したログの日時から集計を始めます
+pub async fn set_replay_time(datetime: DateTime<FixedOffset>) {
+ let mut replay_time = REPLAY_TIME.lock().await;
+ if replay_time.is_none() {
+  *ITEM_COUNTER_BEGIN.lock().await = datetime;
+ }
+ *replay_time = Some(datetime);
+}
+
+/// 集計の現在時刻（リプレイ中は最後に再生したログの日時）
+async fn counter_now() -> DateTime<FixedOffset> {
+ REPLAY_TIME.lock().await.unwrap_or_else(now)
+}
+
+/// CurrentMeseta から記録した所持メセタのスナップショット
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MesetaSnapshot {
+ pub datetime: DateTime<FixedOffset>,
+ /// メセタ入手後の所持メセタ
+ pub balance: u64,
+ /// このログで入手したメセタ
+ pub gained: u64,
+}
+
+/// 所持メセタの推移とメセタの入手額を記録するトラッカー
+#[derive(Debug, Default)]
+pub struct MesetaTracker {
+ snapshots: Vec<MesetaSnapshot>,
+ gross_income: u64,
+ sale_income: u64,
+}
+
+impl MesetaTracker {
+ /// メセタを入手したアイテムログと売却のログを記録します（それ以外のログは無視します）
+ pub fn record(&mut self, item_log: &ItemLog) {
+  if let Some(sale_income) = item_log.get_sale_income() {
+   self.sale_income += sale_income as u64;
+   return;
+  }
+  if item_log.item != "Meseta" || !item_log.category.is_acquisition() {
+   return;
+  }
+  let gained = item_log.count as u64;
+  self.gross_income += gained;
+  if let Some(balance) = item_log.current_meseta {
+   self.snapshots.push(MesetaSnapshot {
+    datetime: item_log.datetime,
+    balance,
+    gained,
+   });
+  }
+ }
+
+ pub fn clear(&mut self) {
+  self.snapshots.clear();
+  self.gross_income = 0;
+  self.sale_income = 0;
+ }
+
+ pub fn snapshots(&self) -> &[MesetaSnapshot] {
+  &self.snapshots
+ }
+
+ /// 入手したメセタの合計（売却やショップの売上は含みません）
+ pub fn gross_income(&self) -> u64 {
+  self.gross_income
+ }
+
+ /// 売却で入手したメセタの合計
+ pub fn sale_income(&self) -> u64 {
+  self.sale_income
+ }
+
+ /// 最初のメセタ入手直前から最新のスナップショットまでの所持メセタの増減
+ ///
+ /// 入手額と売却額との差分で、その間に支払ったメセタが分かります。
+ pub fn net_change(&self) -> Option<i64> {
+  let first = self.snapshots.first()?;
+  let last = self.snapshots.last()?;
+  Some(last.balance as i64 - (first.balance as i64 - first.gained as i64))
+ }
+}
+
+/// 1時間あたりの金額に換算します（経過時間が 0 の場合は None）
+fn per_hour(amount: i64, elapsed: chrono::Duration) -> Option<i64> {
+ match elapsed.num_seconds() {
+  seconds if seconds > 0 => Some(amount * 3600 / seconds),
+  _ => None,
+ }
+}
+
+/// true の場合はすべての [[if]] の sound, command, get, post を実行せずに内容を表示します ( --dry-run )
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+
+pub fn enable_dry_run() {
+ DRY_RUN.store(true, AtomicOrdering::Relaxed);
+}
+
+pub fn is_dry_run() -> bool {
+ DRY_RUN.load(AtomicOrdering::Relaxed)
+}
+
+/// 実行しなかった sound, command, get, post のアクションが行う内容を表示します
+async fn dry_run(action_type: ActionType, details: Vec<String>) -> Result<()> {
+ let mut stdout = StandardStream::stdout(ColorChoice::Always);
+ let color = Some(Color::Ansi256(CONF.get_color_ansi256_system()));
+ stdout.set_color(ColorSpec::new().set_fg(color))?;
+ let mut details = details.iter();
+ writeln!(
+  &mut stdout,
+  "[Action::{:?}]{}(dry-run) {}",
+  action_type,
+  CONF.get_column_separator(),
+  details.next().map_or("", String::as_str)
+ )?;
+ for detail in details {
+  writeln!(&mut stdout, "  {}", detail)?;
+ }
+ Ok(())
+}
 
 pub async fn initialize() {
- ITEM_COUNTER.lock().await;
- ITEM_COUNTER_BEGIN.lock().await;
+ Lazy::force(&ITEM_COUNTER);
+ Lazy::force(&ITEM_COUNTER_BEGIN);
+ Lazy::force(&MESETA_TRACKER);
 }
 
+/// 所持メセタを記録します（アクションの設定に関わらずすべてのログで呼び出します）
+pub async fn track_meseta(ngs_log: &NgsLog) {
+ if let NgsLog::ItemLog(item_log) = ngs_log {
+  MESETA_TRACKER.lock().await.record(item_log);
+ }
+}
+
+/// dry_run が true の場合は sound, command, get, post を実行せずに内容を表示します。
+/// 先の [[if]] で実行済み ( finished_actions に含まれる) のため実行しなかったアクションを返します
 pub async fn do_action(
  action: &Action,
  ngs_log: &NgsLog,
+ variables: &Variables,
  finished_actions: &mut Vec<ActionType>,
-) -> Result<()> {
+ dry_run: bool,
+) -> Result<Vec<ActionType>> {
+ let dry_run = dry_run || is_dry_run();
+ let skipped_actions = action
+  .get_action_types()
+  .into_iter()
+  .filter(|action_type| finished_actions.contains(action_type))
+  .collect();
  // action
  let mut futures = Vec::new();
  if action.show == Some(true) && !finished_actions.contains(&ActionType::Show) {
-  futures.push(show(&ngs_log).boxed());
+  futures.push(show(ngs_log, action.show_format.as_ref(), variables).boxed());
   finished_actions.push(ActionType::Show);
  }
  if !finished_actions.contains(&ActionType::Sound) {
   if let Some(ref sound_file_path) = action.sound {
-   futures.push(sound(&sound_file_path).boxed());
+   futures.push(match dry_run {
+    false => sound(sound_file_path).boxed(),
+    true => self::dry_run(ActionType::Sound, vec![sound_file_path.clone()]).boxed(),
+   });
    finished_actions.push(ActionType::Sound);
   }
  }
  if !finished_actions.contains(&ActionType::Command) {
   if let Some(ref action_command) = action.command {
-   futures.push(command(action_command).boxed());
+   let action_command = resolve_command(action_command, variables);
+   futures.push(match dry_run {
+    false => command(action_command).boxed(),
+    true => self::dry_run(ActionType::Command, vec![format!("{:?}", action_command)]).boxed(),
+   });
    finished_actions.push(ActionType::Command);
   }
  }
  if !finished_actions.contains(&ActionType::Get) {
   if let Some(ref url) = action.get {
-   futures.push(get(&url, &ngs_log).boxed());
+   futures.push(match dry_run {
+    false => get(url, variables).boxed(),
+    true => self::dry_run(ActionType::Get, vec![resolve_get_url(url, variables)]).boxed(),
+   });
    finished_actions.push(ActionType::Get);
   }
  }
  if !finished_actions.contains(&ActionType::Post) {
   if let Some(ref url) = action.post {
-   futures.push(post(&url, &ngs_log).boxed());
+   futures.push(match dry_run {
+    false => post(
+     url,
+     post_body(action, ngs_log, variables),
+     ngs_log,
+     variables,
+    )
+    .boxed(),
+    true => {
+     let mut details = vec![url.clone()];
+     for (key, value) in post_headers(ngs_log, variables) {
+      details.push(format!("{}: {}", key, value));
+     }
+     details.push(format!("body: {:?}", post_body(action, ngs_log, variables)));
+     self::dry_run(ActionType::Post, details).boxed()
+    }
+   });
    finished_actions.push(ActionType::Post);
   }
  }
  if Some(true) == action.count && !finished_actions.contains(&ActionType::Count) {
-  futures.push(count(&ngs_log).boxed());
+  futures.push(count(ngs_log).boxed());
   finished_actions.push(ActionType::Count);
  }
  if Some(true) == action.show_item_counts && !finished_actions.contains(&ActionType::ShowItemCounts)
@@ -95,9 +265,15 @@ pub async fn do_action(
   futures.push(reset_item_counts().boxed());
   finished_actions.push(ActionType::ResetItemCounts);
  }
+ if Some(true) == action.show_meseta_report
+  && !finished_actions.contains(&ActionType::ShowMesetaReport)
+ {
+  futures.push(show_meseta_report().boxed());
+  finished_actions.push(ActionType::ShowMesetaReport);
+ }
 
  join_all(futures).await;
- Ok(())
+ Ok(skipped_actions)
 }
 
 pub async fn count(ngs_log: &NgsLog) -> Result<()> {
@@ -105,18 +281,30 @@ pub async fn count(ngs_log: &NgsLog) -> Result<()> {
   *ITEM_COUNTER
    .lock()
    .await
-   .entry(item_log.item.clone())
+   .entry(item_log.get_counter_key())
    .or_insert(Counter {
     current: 0,
     prev: 0,
    }) += item_log.count;
+  // 売却額は拾ったメセタと区別して集計します
+  if let Some(sale_income) = item_log.get_sale_income() {
+   *ITEM_COUNTER
+    .lock()
+    .await
+    .entry(format!("Meseta [{}]", item_log.category))
+    .or_insert(Counter {
+     current: 0,
+     prev: 0,
+    }) += sale_income;
+  }
  }
  Ok(())
 }
 
 pub async fn reset_item_counts() -> Result<()> {
  ITEM_COUNTER.lock().await.clear();
- *ITEM_COUNTER_BEGIN.lock().await = now();
+ MESETA_TRACKER.lock().await.clear();
+ *ITEM_COUNTER_BEGIN.lock().await = counter_now().await;
  let mut stdout = StandardStream::stdout(ColorChoice::Always);
  let color = Some(Color::Ansi256(CONF.get_color_ansi256_item()));
  stdout.set_color(ColorSpec::new().set_fg(color))?;
@@ -129,8 +317,8 @@ pub async fn reset_item_counts() -> Result<()> {
 }
 
 pub async fn show_item_counts() -> Result<()> {
- let begin = ITEM_COUNTER_BEGIN.lock().await.clone();
- let now = now();
+ let begin = *ITEM_COUNTER_BEGIN.lock().await;
+ let now = counter_now().await;
  let dt = now - begin;
  let dt = format!(
   r#"{:02}°{:02}'{:02}""#,
@@ -185,9 +373,77 @@ pub async fn show_item_counts() -> Result<()> {
  Ok(())
 }
 
-pub async fn show(ngs_log: &NgsLog) -> Result<()> {
+pub async fn show_meseta_report() -> Result<()> {
+ let begin = *ITEM_COUNTER_BEGIN.lock().await;
+ let now = counter_now().await;
+ let elapsed = now - begin;
+ let tracker = MESETA_TRACKER.lock().await;
+ let format_amount = |amount: i64| {
+  let sign = if amount < 0 { "-" } else { "" };
+  format!(
+   "{}{}",
+   sign,
+   amount.unsigned_abs().to_formatted_string(&Locale::ja)
+  )
+ };
+ let gross_income = tracker.gross_income() as i64;
+ let sale_income = tracker.sale_income() as i64;
+ let net_change = tracker.net_change();
  let mut stdout = StandardStream::stdout(ColorChoice::Always);
- let color = Some(Color::Ansi256(CONF.get_color_ansi256(&ngs_log)));
+ let color = Some(Color::Ansi256(CONF.get_color_ansi256_item()));
+ stdout.set_color(ColorSpec::new().set_fg(color))?;
+ writeln!(
+  &mut stdout,
+  "=== メセタ集計: {} -> {} ===",
+  format_datetime(&begin),
+  format_datetime(&now)
+ )
+ .unwrap();
+ writeln!(
+  &mut stdout,
+  "入手メセタ: {} ( {} / 時間 )",
+  format_amount(gross_income),
+  per_hour(gross_income, elapsed).map_or("-".to_string(), format_amount)
+ )
+ .unwrap();
+ writeln!(
+  &mut stdout,
+  "売却メセタ: {} ( {} / 時間 )",
+  format_amount(sale_income),
+  per_hour(sale_income, elapsed).map_or("-".to_string(), format_amount)
+ )
+ .unwrap();
+ match (net_change, tracker.snapshots().last()) {
+  (Some(net_change), Some(last)) => writeln!(
+   &mut stdout,
+   "所持メセタの増減: {} ( {} / 時間 ) 所持メセタ: {}",
+   format_amount(net_change),
+   per_hour(net_change, elapsed).map_or("-".to_string(), format_amount),
+   format_amount(last.balance as i64)
+  )
+  .unwrap(),
+  _ => writeln!(
+   &mut stdout,
+   "所持メセタの増減: - ( 所持メセタの記録がありません )"
+  )
+  .unwrap(),
+ }
+ writeln!(
+  &mut stdout,
+  "============================================================="
+ )
+ .unwrap();
+ Ok(())
+}
+
+/// show_format が設定されている場合は本文の代わりに変数を埋め込んだ show_format を表示します
+pub async fn show(
+ ngs_log: &NgsLog,
+ show_format: Option<&String>,
+ variables: &Variables,
+) -> Result<()> {
+ let mut stdout = StandardStream::stdout(ColorChoice::Always);
+ let color = Some(Color::Ansi256(CONF.get_color_ansi256(ngs_log)));
  stdout.set_color(ColorSpec::new().set_fg(color))?;
 
  let column_separator = CONF.get_column_separator();
@@ -200,16 +456,13 @@ pub async fn show(ngs_log: &NgsLog) -> Result<()> {
 
  let datetime_part = format!(
   "{}{}",
-  format_datetime(&ngs_log.get_datetime()),
+  format_datetime(ngs_log.get_datetime()),
   column_separator
  );
 
  let channel_stringify = || {
   let channel_padding_width = match CONF.global {
-   Some(ref global) => match global.channel_padding_width {
-    Some(channel_padding_width) => channel_padding_width,
-    _ => 6,
-   },
+   Some(ref global) => global.channel_padding_width.unwrap_or(6),
    _ => 6,
   };
   format!(
@@ -229,10 +482,7 @@ pub async fn show(ngs_log: &NgsLog) -> Result<()> {
  };
 
  let name_padding_width = match CONF.global {
-  Some(ref global) => match global.name_padding_width {
-   Some(name_padding_width) => name_padding_width,
-   _ => 30,
-  },
+  Some(ref global) => global.name_padding_width.unwrap_or(30),
   _ => 30,
  };
 
@@ -246,15 +496,16 @@ pub async fn show(ngs_log: &NgsLog) -> Result<()> {
   action_pattern_part, datetime_part, channel_part, name_part,
  );
 
+ let body = show_format.map_or(ngs_log.get_body_or_item_with_count(), |show_format| {
+  variables.render_plain(show_format)
+ });
  let output_body_part = if CONF.get_pretty_multiline() {
   let output_first_part_unicode_width = UnicodeWidthStr::width(&output_first_part[..]);
   let padding = " ".repeat(output_first_part_unicode_width);
   let replacement = format!("\n{}", padding);
-  ngs_log
-   .get_body_or_item_with_count()
-   .replacen("\n", &replacement, 3)
+  body.replacen("\n", &replacement, 3)
  } else {
-  ngs_log.get_body_or_item_with_count()
+  body
  };
 
  writeln!(&mut stdout, "{}{}", output_first_part, output_body_part,)?;
@@ -274,16 +525,43 @@ pub async fn sound(sound_file_path: &str) -> Result<()> {
   sound_file_path
  )?;
 
+ play_sound(sound_file_path)
+}
+
+#[cfg(windows)]
+fn play_sound(sound_file_path: &str) -> Result<()> {
  // let sound_file_path = sound_file_path.clone();
  let mut player = winaudio::wave::Player::from_file(sound_file_path).unwrap();
- let _ = spawn(async move {
+ let _ = tokio::spawn(async move {
   player.play().unwrap();
  });
 
  Ok(())
 }
 
-pub async fn command(command: &Vec<String>) -> Result<()> {
+/// winaudio は Windows 専用のため、それ以外の環境では音を鳴らしません
+#[cfg(not(windows))]
+fn play_sound(_sound_file_path: &str) -> Result<()> {
+ Ok(())
+}
+
+/// command の各要素の {body} などを変数の値に置き換えます（ get と同じ変数を使えます）
+fn resolve_command(command: &[String], variables: &Variables) -> Vec<String> {
+ command
+  .iter()
+  .map(|argument| variables.render_plain(argument))
+  .collect()
+}
+
+/// command の先頭をプログラム、残りをその引数とするコマンド
+fn build_command(command: &[String]) -> Option<Command> {
+ let (program, arguments) = command.split_first()?;
+ let mut command = Command::new(program);
+ command.args(arguments);
+ Some(command)
+}
+
+pub async fn command(command: Vec<String>) -> Result<()> {
  let mut stdout = StandardStream::stdout(ColorChoice::Always);
  let color = Some(Color::Ansi256(CONF.get_color_ansi256_system()));
  stdout.set_color(ColorSpec::new().set_fg(color))?;
@@ -295,44 +573,24 @@ pub async fn command(command: &Vec<String>) -> Result<()> {
   command
  )?;
 
- if let Some(c) = command.first() {
-  if command.len() > 2 {
-   let _output = Command::new(c).args(&command[1..]).output()?;
-  } else {
-   let _output = Command::new(c).output()?;
-  }
+ if let Some(mut command) = build_command(&command) {
+  let _output = command.output()?;
  }
 
  Ok(())
 }
 
-pub async fn get(url: &str, ngs_log: &NgsLog) -> Result<()> {
+/// get アクションの URL の {name} などを URL エンコードした変数の値に置き換えます
+fn resolve_get_url(url: &str, variables: &Variables) -> String {
+ variables.render(url, urlencoding::encode)
+}
+
+pub async fn get(url: &str, variables: &Variables) -> Result<()> {
  let mut stdout = StandardStream::stdout(ColorChoice::Always);
  let color = Some(Color::Ansi256(CONF.get_color_ansi256_system()));
  stdout.set_color(ColorSpec::new().set_fg(color))?;
 
- let url = url
-  .replace(
-   "{body}",
-   &urlencoding::encode(&ngs_log.get_body_or_item_with_count()).to_string(),
-  )
-  .replace(
-   "{name}",
-   &urlencoding::encode(&ngs_log.get_name()).to_string(),
-  )
-  .replace(
-   "{channel}",
-   &urlencoding::encode(&format!(
-    "{:?}",
-    ngs_log
-     .get_channel()
-     .map_or("ITEM".to_string(), |c| format!("{:?}", c))
-   )),
-  )
-  .replace(
-   "{datetime}",
-   &urlencoding::encode(&format!("{:?}", ngs_log.get_datetime())),
-  );
+ let url = resolve_get_url(url, variables);
 
  let mut response = surf::get(&url)
   .header("user-agent", "NGS Log Action")
@@ -365,17 +623,65 @@ pub async fn get(url: &str, ngs_log: &NgsLog) -> Result<()> {
  Ok(())
 }
 
-pub async fn post(url: &str, ngs_log: &NgsLog) -> Result<()> {
+/// post アクションで送信する本文。 post_body が設定されている場合は変数を埋め込んだ post_body です
+fn post_body(action: &Action, ngs_log: &NgsLog, variables: &Variables) -> String {
+ action
+  .post_body
+  .as_ref()
+  .map_or(ngs_log.get_body_or_item_with_count(), |post_body| {
+   variables.render_plain(post_body)
+  })
+}
+
+/// post アクションで送信するログの情報と [[if]] の評価で求めた変数のヘッダー
+fn post_headers(ngs_log: &NgsLog, variables: &Variables) -> Vec<(String, String)> {
+ let mut headers = vec![
+  (
+   "ngs-log-action-name".to_string(),
+   urlencoding::encode(ngs_log.get_name()).to_string(),
+  ),
+  (
+   "ngs-log-action-channel".to_string(),
+   format!(
+    "{:?}",
+    ngs_log
+     .get_channel()
+     .map_or("ITEM".to_string(), |c| format!("{:?}", c))
+   ),
+  ),
+  (
+   "ngs-log-action-datetime".to_string(),
+   ngs_log.get_datetime().to_string(),
+  ),
+ ];
+ if let Some(item_log) = ngs_log.get_item_log() {
+  for (key, value) in item_log.get_properties() {
+   headers.push((
+    format!("ngs-log-action-{}", key.replace('_', "-")),
+    urlencoding::encode(&value).to_string(),
+   ));
+  }
+ }
+ for (key, value) in variables.extra() {
+  headers.push((
+   format!("ngs-log-action-{}", key.replace('_', "-")),
+   urlencoding::encode(value).to_string(),
+  ));
+ }
+ headers
+}
+
+pub async fn post(url: &str, body: String, ngs_log: &NgsLog, variables: &Variables) -> Result<()> {
  let mut stdout = StandardStream::stdout(ColorChoice::Always);
  let color = Some(Color::Ansi256(CONF.get_color_ansi256_system()));
  stdout.set_color(ColorSpec::new().set_fg(color))?;
 
- let mut response = surf::post(url)
-  .header("user-agent", "NGS Log Action")
-  .header("ngs-log-action-name", urlencoding::encode(&ngs_log.get_name()))
-  .header("ngs-log-action-channel", format!("{:?}", ngs_log.get_channel().map_or("ITEM".to_string(), |c|format!("{:?}",c))))
-  .header("ngs-log-action-datetime", ngs_log.get_datetime().to_string())
-  .body(ngs_log.get_body_or_item_with_count())
+ let mut request = surf::post(url).header("user-agent", "NGS Log Action");
+ for (key, value) in post_headers(ngs_log, variables) {
+  request = request.header(key.as_str(), value);
+ }
+ let mut response = request
+  .body(body)
   .await
   .unwrap()
   // .map_err(|_| NgsLogActionError::ErrorCode(510))?
@@ -406,3 +712,160 @@ pub async fn post(url: &str, ngs_log: &NgsLog) -> Result<()> {
 
  Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+ use super::*;
+ use crate::ngs_log::ItemCategory;
+ use chrono::TimeZone;
+
+ fn meseta_log(category: ItemCategory, count: u32, current_meseta: Option<u64>) -> ItemLog {
+  ItemLog {
+   datetime: FixedOffset::east_opt(9 * 3600)
+    .unwrap()
+    .with_ymd_and_hms(2021, 8, 19, 20, 40, 56)
+    .unwrap(),
+   log_id: 0,
+   category,
+   player_id: 0,
+   name: "L,A.M.".to_string(),
+   item: "Meseta".to_string(),
+   count,
+   level: None,
+   attribute: None,
+   current_meseta,
+   meseta: None,
+   destination: None,
+  }
+ }
+
+ #[test]
+ fn meseta_tracker_reports_gross_income_and_net_change() {
+  let mut tracker = MesetaTracker::default();
+  assert_eq!(tracker.net_change(), None);
+  tracker.record(&meseta_log(ItemCategory::Pickup, 12, Some(1012)));
+  tracker.record(&meseta_log(ItemCategory::Reward, 1500, None));
+  // 1012 + 1500 から 500 支払った後に 100 拾った
+  tracker.record(&meseta_log(ItemCategory::Pickup, 100, Some(2112)));
+  // 売却額は入手メセタに含めません
+  let mut sell = meseta_log(ItemCategory::Sell, 1, None);
+  sell.item = "ツヴィアダガー".to_string();
+  sell.meseta = Some(120);
+  tracker.record(&sell);
+  assert_eq!(tracker.gross_income(), 1612);
+  assert_eq!(tracker.sale_income(), 120);
+  assert_eq!(tracker.net_change(), Some(1112));
+  assert_eq!(tracker.snapshots().len(), 2);
+  tracker.clear();
+  assert_eq!(tracker.gross_income(), 0);
+  assert_eq!(tracker.sale_income(), 0);
+  assert_eq!(tracker.net_change(), None);
+ }
+
+ #[test]
+ fn per_hour_scales_by_elapsed_time() {
+  assert_eq!(per_hour(1000, chrono::Duration::minutes(30)), Some(2000));
+  assert_eq!(per_hour(-300, chrono::Duration::hours(3)), Some(-100));
+  assert_eq!(per_hour(1000, chrono::Duration::zero()), None);
+ }
+
+ #[test]
+ fn get_url_and_post_headers_are_resolved_from_log() {
+  let chat_log = crate::ngs_log::chat_log(
+   crate::ngs_log::NgsLogChannel::Party,
+   "L,A.M.",
+   "よろしく & お願いします",
+  );
+  assert_eq!(
+   resolve_get_url(
+    "http://localhost/?n={name}&b={body}&i={item}",
+    &Variables::from_log(&chat_log)
+   ),
+   "http://localhost/?n=L%2CA.M.&b=%E3%82%88%E3%82%8D%E3%81%97%E3%81%8F%20%26%20%E3%81%8A%E9%A1%98%E3%81%84%E3%81%97%E3%81%BE%E3%81%99&i="
+  );
+  let mut variables = Variables::from_log(&chat_log);
+  variables.insert_elapsed(chrono::Duration::seconds(754));
+  let headers = post_headers(&chat_log, &variables);
+  assert_eq!(
+   headers[0],
+   ("ngs-log-action-name".to_string(), "L%2CA.M.".to_string())
+  );
+  assert_eq!(
+   headers[1],
+   (
+    "ngs-log-action-channel".to_string(),
+    "\"Party\"".to_string()
+   )
+  );
+  assert!(headers.contains(&(
+   "ngs-log-action-elapsed-seconds".to_string(),
+   "754".to_string()
+  )));
+
+  let item_log = NgsLog::ItemLog(meseta_log(ItemCategory::Pickup, 12, Some(1012)));
+  assert_eq!(
+   resolve_get_url(
+    "http://localhost/?c={count}&m={current_meseta}",
+    &Variables::from_log(&item_log)
+   ),
+   "http://localhost/?c=12&m=1012"
+  );
+  assert!(
+   post_headers(&item_log, &Variables::from_log(&item_log)).contains(&(
+    "ngs-log-action-current-meseta".to_string(),
+    "1012".to_string()
+   ))
+  );
+
+  let mut sell = meseta_log(ItemCategory::Sell, 1, None);
+  sell.meseta = Some(120);
+  let sell = NgsLog::ItemLog(sell);
+  assert_eq!(
+   resolve_get_url("http://localhost/?m={meseta}", &Variables::from_log(&sell)),
+   "http://localhost/?m=120"
+  );
+  assert!(post_headers(&sell, &Variables::from_log(&sell))
+   .contains(&("ngs-log-action-meseta".to_string(), "120".to_string())));
+ }
+
+ #[test]
+ fn command_arguments_are_resolved_and_kept() {
+  let item_log = NgsLog::ItemLog(meseta_log(ItemCategory::Pickup, 12, Some(1012)));
+  let command = vec![
+   "notify-send".to_string(),
+   "{name}: {item} × {count} ({body})".to_string(),
+  ];
+  let command = resolve_command(&command, &Variables::from_log(&item_log));
+  assert_eq!(
+   command,
+   vec!["notify-send", "L,A.M.: Meseta × 12 (Meseta × 12)"]
+  );
+  let built = build_command(&command).unwrap();
+  assert_eq!(built.get_program(), "notify-send");
+  assert_eq!(
+   built.get_args().collect::<Vec<_>>(),
+   vec!["L,A.M.: Meseta × 12 (Meseta × 12)"]
+  );
+  let built = build_command(&["calc".to_string()]).unwrap();
+  assert_eq!(built.get_args().count(), 0);
+  assert!(build_command(&[]).is_none());
+ }
+
+ #[test]
+ fn post_body_escapes_json_variables() {
+  let action: Action = toml::from_str(
+   r#"
+post = "http://localhost/"
+post_body = '{"boss": "{boss:json}", "raw": "{boss}", "count": {count:json}}'
+"#,
+  )
+  .unwrap();
+  let item_log = NgsLog::ItemLog(meseta_log(ItemCategory::Pickup, 12, Some(1012)));
+  let mut variables = Variables::from_log(&item_log);
+  variables.insert("boss", "\"ダークファルス\"\n\\");
+  assert_eq!(
+   post_body(&action, &item_log, &variables),
+   "{\"boss\": \"\\\"ダークファルス\\\"\\n\\\\\", \"raw\": \"\"ダークファルス\"\n\\\", \"count\": 12}"
+  );
+ }
+}
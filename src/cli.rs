@@ -0,0 +1,45 @@
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+/// コマンドライン引数
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Args {
+ /// ログフォルダ（log_ngs と log を含むフォルダ）。 conf.toml の log_roots や自動検出より優先します
+ #[arg(long, value_name = "DIR", global = true)]
+ pub log_dir: Option<PathBuf>,
+ /// sound, command, get, post のアクションを実行せずに、実行する内容（置き換え後の URL など）を表示します
+ #[arg(long, global = true)]
+ pub dry_run: bool,
+ /// ログごとにすべての [[if]] の評価結果（最初に満たさなかった条件、実行済みのため実行しなかったアクション）を表示します
+ #[arg(long, global = true)]
+ pub explain: bool,
+ #[command(subcommand)]
+ pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+ /// 過去のログを時系列順に [[if]] の設定へ流して、設定の動作を確認します
+ Replay(ReplayArgs),
+}
+
+#[derive(Debug, clap::Args)]
+pub struct ReplayArgs {
+ /// ログフォルダ（log_ngs と log を含むフォルダ）、 log_ngs などのフォルダ、またはログファイル。
+ /// 未指定の場合は --log-dir などで決まるログフォルダを使います
+ #[arg(value_name = "PATH")]
+ pub paths: Vec<PathBuf>,
+ /// この日時以降のログを再生します 例: 2021-08-19T20:00:00
+ #[arg(long, value_name = "DATETIME")]
+ pub from: Option<String>,
+ /// この日時以前のログを再生します 例: 2021-08-19T23:59:59
+ #[arg(long, value_name = "DATETIME")]
+ pub to: Option<String>,
+ /// ログの間隔どおりに待ちながら再生する倍率 ( 1 で実時間、 10 で10倍速 ) 。未指定の場合は待たずに再生します
+ #[arg(long)]
+ pub speed: Option<f64>,
+ /// --dry-run と同じく sound, command, get, post のアクションを実行せずに内容を表示します
+ #[arg(long)]
+ pub no_side_effects: bool,
+}
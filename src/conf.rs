@@ -1,5 +1,9 @@
-use crate::ngs_log::{NgsLog, NgsLogChannel};
+use crate::ngs_log::{ItemCategory, ItemDestination, NgsLog, NgsLogChannel};
+use crate::rate_limit::LimitScope;
+use crate::timezone::LogTimezone;
+use anyhow::Result;
 use serde::Deserialize;
+use std::path::PathBuf;
 use strum_macros::EnumString;
 
 #[derive(Debug, Deserialize)]
@@ -25,6 +29,10 @@ pub struct Global {
  pub color_system: Option<u8>,
  pub polling_rate: Option<f64>,
  pub pretty_multiline: Option<bool>,
+ pub log_roots: Option<Vec<PathBuf>>,
+ pub watch: Option<bool>,
+ pub log_timezone: Option<String>,
+ pub trace: Option<bool>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -38,7 +46,51 @@ pub struct If {
  pub ignore_regex: Option<String>,
  pub action: Option<Action>,
  pub target: Option<Target>,
+ pub categories: Option<Vec<ItemCategory>>,
+ pub min_level: Option<u32>,
+ pub max_level: Option<u32>,
+ pub attributes: Option<Vec<String>>,
+ pub player_ids: Option<Vec<u32>>,
+ pub destinations: Option<Vec<ItemDestination>>,
  pub item_counts: Option<Vec<ItemCount>>,
+ /// true の場合はこの [[if]] の sound, command, get, post を実行せずに内容を表示します
+ pub dry_run: Option<bool>,
+ pub when: Option<Condition>,
+ /// "20:00-02:00" のような時間帯（ログの日時で判定します）
+ pub active_hours: Option<Vec<String>>,
+ /// "Mon" や "月" のような曜日
+ pub weekdays: Option<Vec<String>>,
+ /// "30s" のような、発動してから次に発動できるまでの期間
+ pub cooldown: Option<String>,
+ /// 1分間に発動できる回数
+ pub max_per_minute: Option<u32>,
+ /// cooldown と max_per_minute を数える単位
+ pub limit_scope: Option<LimitScope>,
+ /// "2m" のような、 min_count を数える期間
+ pub within: Option<String>,
+ /// within の期間内に条件を満たしたログがこの件数に達したら発動します
+ pub min_count: Option<u32>,
+ /// true の場合は min_count を発言者の人数で数えます
+ pub distinct_names: Option<bool>,
+ /// min_count で発動した後、再び数え始めるまでに条件を満たすログが途切れる期間（既定は within ）
+ pub quiet: Option<String>,
+ /// 順番に満たすと発動する条件（ within が設定されている場合はその期間内）
+ pub sequence: Option<Vec<Condition>>,
+}
+
+/// when の条件式。 any, all, not と names などの項目を組み合わせられます。
+/// 1つの条件に複数の項目を設定した場合はすべてを満たす必要があります
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Condition {
+ pub any: Option<Vec<Condition>>,
+ pub all: Option<Vec<Condition>>,
+ pub not: Option<Box<Condition>>,
+ pub target: Option<Target>,
+ pub names: Option<Vec<String>>,
+ pub channels: Option<Vec<NgsLogChannel>>,
+ pub keywords: Option<Vec<String>>,
+ pub regex: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -58,9 +110,14 @@ pub struct Action {
  pub count: Option<bool>,
  pub show_item_counts: Option<bool>,
  pub reset_item_counts: Option<bool>,
+ pub show_meseta_report: Option<bool>,
+ /// show で本文の代わりに表示する {name} などの変数を埋め込んだ文字列
+ pub show_format: Option<String>,
+ /// post で本文の代わりに送信する {name} などの変数を埋め込んだ文字列
+ pub post_body: Option<String>,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ActionType {
  Show,
  Command,
@@ -70,12 +127,64 @@ pub enum ActionType {
  Count,
  ShowItemCounts,
  ResetItemCounts,
+ ShowMesetaReport,
+}
+
+impl Action {
+ /// 設定されているアクションの種類
+ pub fn get_action_types(&self) -> Vec<ActionType> {
+  let mut action_types = Vec::new();
+  if self.show == Some(true) {
+   action_types.push(ActionType::Show);
+  }
+  if self.sound.is_some() {
+   action_types.push(ActionType::Sound);
+  }
+  if self.command.is_some() {
+   action_types.push(ActionType::Command);
+  }
+  if self.get.is_some() {
+   action_types.push(ActionType::Get);
+  }
+  if self.post.is_some() {
+   action_types.push(ActionType::Post);
+  }
+  if self.count == Some(true) {
+   action_types.push(ActionType::Count);
+  }
+  if self.show_item_counts == Some(true) {
+   action_types.push(ActionType::ShowItemCounts);
+  }
+  if self.reset_item_counts == Some(true) {
+   action_types.push(ActionType::ResetItemCounts);
+  }
+  if self.show_meseta_report == Some(true) {
+   action_types.push(ActionType::ShowMesetaReport);
+  }
+  action_types
+ }
 }
 
 #[derive(Debug, EnumString, Deserialize, PartialEq, Eq)]
 pub enum Target {
  Chat,
  Item,
+ SymbolChat,
+ Scratch,
+ StarGem,
+}
+
+impl Target {
+ pub fn matches(&self, ngs_log: &NgsLog) -> bool {
+  matches!(
+   (self, ngs_log),
+   (Target::Chat, NgsLog::ChatLog(_))
+    | (Target::Item, NgsLog::ItemLog(_))
+    | (Target::SymbolChat, NgsLog::SymbolChatLog(_))
+    | (Target::Scratch, NgsLog::ScratchLog(_))
+    | (Target::StarGem, NgsLog::StarGemLog(_))
+  )
+ }
 }
 
 // Default-Colors
@@ -88,6 +197,7 @@ const DC_ITEM: u8 = 227;
 const DC_SYSTEM: u8 = 8;
 const DEFAULT_POLLING_RATE: f64 = 1.0;
 const DEFAULT_PRETTY_MULTILINE: bool = true;
+const DEFAULT_WATCH: bool = true;
 
 impl Conf {
  pub fn get_pretty_multiline(&self) -> bool {
@@ -96,6 +206,36 @@ impl Conf {
   })
  }
 
+ pub fn get_log_roots(&self) -> Vec<PathBuf> {
+  self
+   .global
+   .as_ref()
+   .and_then(|g| g.log_roots.clone())
+   .unwrap_or_default()
+ }
+
+ /// log_timezone 未指定の場合は OS のタイムゾーンを使います
+ pub fn get_log_timezone(&self) -> Result<LogTimezone> {
+  match self.global.as_ref().and_then(|g| g.log_timezone.as_ref()) {
+   Some(name) => LogTimezone::from_name(name),
+   None => Ok(LogTimezone::Local),
+  }
+ }
+
+ pub fn get_trace(&self) -> bool {
+  self
+   .global
+   .as_ref()
+   .is_some_and(|g| g.trace.unwrap_or(false))
+ }
+
+ pub fn get_watch(&self) -> bool {
+  self
+   .global
+   .as_ref()
+   .map_or(DEFAULT_WATCH, |g| g.watch.unwrap_or(DEFAULT_WATCH))
+ }
+
  pub fn get_polling_rate(&self) -> f64 {
   self.global.as_ref().map_or(DEFAULT_POLLING_RATE, |g| {
    g.polling_rate.unwrap_or(DEFAULT_POLLING_RATE)
@@ -117,8 +257,8 @@ impl Conf {
  }
 
  pub fn get_color_ansi256(&self, ngs_log: &NgsLog) -> u8 {
-  match ngs_log {
-   NgsLog::ChatLog(l) => match l.channel {
+  match ngs_log.get_channel() {
+   Some(channel) => match channel {
     NgsLogChannel::Public => self
      .global
      .as_ref()
@@ -140,7 +280,7 @@ impl Conf {
      .as_ref()
      .map_or(DC_REPLY, |g| g.color_reply.unwrap_or(DC_REPLY)),
    },
-   NgsLog::ItemLog(_) => self
+   None => self
     .global
     .as_ref()
     .map_or(DC_ITEM, |g| g.color_item.unwrap_or(DC_ITEM)),
@@ -1,7 +1,25 @@
+use crate::parser::ParseError;
+use std::path::PathBuf;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
 pub enum NgsLogActionError {
  #[error("error-code: {0}")]
  ErrorCode(u32),
+ #[error("ログフォルダ（log_ngs を含むフォルダ）が見つかりません。 --log-dir か conf.toml の log_roots で指定してください: {candidates:?}")]
+ LogDirectoryNotFound { candidates: Vec<PathBuf> },
+ #[error("conf.toml の [[if]] #{index} の {field} が正しくありません: {message}")]
+ InvalidRule {
+  index: usize,
+  field: String,
+  message: String,
+ },
+ #[error("{}:{}: {} => {:?}", .file.display(), .line_number, .source, .raw_line)]
+ MalformedLine {
+  file: PathBuf,
+  line_number: u64,
+  column: Option<&'static str>,
+  raw_line: String,
+  source: ParseError,
+ },
 }
@@ -0,0 +1,444 @@
+use crate::conf::{Condition, Target};
+use crate::error::NgsLogActionError;
+use crate::ngs_log::{NgsLog, NgsLogChannel};
+use aho_corasick::AhoCorasick;
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, Weekday};
+use regex::Regex;
+
+/// index 番目（0 始まり）の [[if]] の field の設定が不正であることを表すエラー
+pub fn invalid_rule(index: usize, field: String, error: impl ToString) -> NgsLogActionError {
+ NgsLogActionError::InvalidRule {
+  index: index + 1,
+  field,
+  message: error.to_string(),
+ }
+}
+
+pub fn compile_keywords(
+ index: usize,
+ field: &str,
+ keywords: &Option<Vec<String>>,
+) -> Result<Option<AhoCorasick>, NgsLogActionError> {
+ keywords
+  .as_ref()
+  .map(|keywords| AhoCorasick::new(keywords).map_err(|e| invalid_rule(index, field.to_string(), e)))
+  .transpose()
+}
+
+pub fn compile_regex(
+ index: usize,
+ field: &str,
+ regex: &Option<String>,
+) -> Result<Option<Regex>, NgsLogActionError> {
+ regex
+  .as_ref()
+  .map(|regex| Regex::new(regex).map_err(|e| invalid_rule(index, field.to_string(), e)))
+  .transpose()
+}
+
+/// "30s", "15m", "1h30m" のような期間を解釈します（単位は d, h, m, s ）
+pub fn parse_duration(duration: &str) -> Result<Duration, String> {
+ let invalid = || {
+  format!(
+   "{:?} は \"30s\" や \"15m\" のような期間ではありません",
+   duration
+  )
+ };
+ let mut total = Duration::zero();
+ let mut number = String::new();
+ for c in duration.trim().chars() {
+  if c.is_ascii_digit() {
+   number.push(c);
+   continue;
+  }
+  let value: i64 = number.parse().map_err(|_| invalid())?;
+  number.clear();
+  let value = match c {
+   'd' => Duration::try_days(value),
+   'h' => Duration::try_hours(value),
+   'm' => Duration::try_minutes(value),
+   's' => Duration::try_seconds(value),
+   _ => return Err(invalid()),
+  };
+  // 大きすぎる値は Duration の範囲を超えるためエラーにします
+  total = value
+   .and_then(|value| total.checked_add(&value))
+   .ok_or_else(invalid)?;
+ }
+ match number.is_empty() && total > Duration::zero() {
+  true => Ok(total),
+  false => Err(invalid()),
+ }
+}
+
+pub fn compile_duration(
+ index: usize,
+ field: &str,
+ duration: &Option<String>,
+) -> Result<Option<Duration>, NgsLogActionError> {
+ duration
+  .as_ref()
+  .map(|duration| parse_duration(duration).map_err(|e| invalid_rule(index, field.to_string(), e)))
+  .transpose()
+}
+
+/// active_hours の1つの時間帯（開始を含み終了を含まない）。終了が開始以前の場合は日付をまたぎます
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HourRange {
+ start: NaiveTime,
+ end: NaiveTime,
+}
+
+impl HourRange {
+ /// "20:00-02:00" のような時間帯を解釈します。終了の "24:00" は日付の終わりです
+ pub fn parse(range: &str) -> Result<Self, String> {
+  let parse_time = |time: &str| match time.trim() {
+   "24:00" => Ok(NaiveTime::MIN),
+   time => NaiveTime::parse_from_str(time, "%H:%M")
+    .map_err(|e| format!("{:?} を時刻として解釈できません ( {} )", time, e)),
+  };
+  match range.split_once('-') {
+   Some((start, end)) => Ok(HourRange {
+    start: parse_time(start)?,
+    end: parse_time(end)?,
+   }),
+   None => Err(format!(
+    "{:?} は \"20:00-02:00\" のような時間帯ではありません",
+    range
+   )),
+  }
+ }
+
+ /// 時間帯に含まれる場合は時間帯が始まった日（日付をまたいだ後は前日）を返します
+ pub fn start_date(&self, datetime: &NaiveDateTime) -> Option<NaiveDate> {
+  let time = datetime.time();
+  let date = datetime.date();
+  if self.start < self.end {
+   (self.start <= time && time < self.end).then_some(date)
+  } else if self.start <= time {
+   Some(date)
+  } else if time < self.end {
+   date.pred_opt()
+  } else {
+   None
+  }
+ }
+}
+
+pub fn compile_hour_ranges(
+ index: usize,
+ active_hours: &Option<Vec<String>>,
+) -> Result<Option<Vec<HourRange>>, NgsLogActionError> {
+ active_hours
+  .as_ref()
+  .map(|active_hours| {
+   active_hours
+    .iter()
+    .enumerate()
+    .map(|(i, range)| {
+     HourRange::parse(range).map_err(|e| invalid_rule(index, format!("active_hours #{}", i + 1), e))
+    })
+    .collect()
+  })
+  .transpose()
+}
+
+/// "Mon", "monday" や "月" のような曜日を解釈します
+pub fn parse_weekday(weekday: &str) -> Result<Weekday, String> {
+ match weekday.trim() {
+  "月" | "月曜" | "月曜日" => Ok(Weekday::Mon),
+  "火" | "火曜" | "火曜日" => Ok(Weekday::Tue),
+  "水" | "水曜" | "水曜日" => Ok(Weekday::Wed),
+  "木" | "木曜" | "木曜日" => Ok(Weekday::Thu),
+  "金" | "金曜" | "金曜日" => Ok(Weekday::Fri),
+  "土" | "土曜" | "土曜日" => Ok(Weekday::Sat),
+  "日" | "日曜" | "日曜日" => Ok(Weekday::Sun),
+  weekday => weekday
+   .parse()
+   .map_err(|_| format!("{:?} を曜日として解釈できません", weekday)),
+ }
+}
+
+pub fn compile_weekdays(
+ index: usize,
+ weekdays: &Option<Vec<String>>,
+) -> Result<Option<Vec<Weekday>>, NgsLogActionError> {
+ weekdays
+  .as_ref()
+  .map(|weekdays| {
+   weekdays
+    .iter()
+    .enumerate()
+    .map(|(i, weekday)| {
+     parse_weekday(weekday).map_err(|e| invalid_rule(index, format!("weekdays #{}", i + 1), e))
+    })
+    .collect()
+  })
+  .transpose()
+}
+
+/// active_hours と weekdays を満たすかを判定し、満たさない場合はその設定項目名を返します。
+/// 日付をまたぐ時間帯の翌日分は、時間帯が始まった日の曜日で判定します
+pub fn find_failing_schedule(
+ active_hours: Option<&[HourRange]>,
+ weekdays: Option<&[Weekday]>,
+ datetime: &NaiveDateTime,
+) -> Option<&'static str> {
+ let start_dates: Vec<NaiveDate> = match active_hours {
+  Some(active_hours) => active_hours
+   .iter()
+   .filter_map(|range| range.start_date(datetime))
+   .collect(),
+  None => vec![datetime.date()],
+ };
+ if start_dates.is_empty() {
+  return Some("active_hours");
+ }
+ match weekdays {
+  Some(weekdays)
+   if !start_dates
+    .iter()
+    .any(|date| weekdays.contains(&date.weekday())) =>
+  {
+   Some("weekdays")
+  }
+  _ => None,
+ }
+}
+
+/// when の条件式を構築したフィルター
+#[derive(Debug)]
+pub enum Filter<'a> {
+ Any(Vec<Filter<'a>>),
+ All(Vec<Filter<'a>>),
+ Not(Box<Filter<'a>>),
+ Target(&'a Target),
+ Names(&'a [String]),
+ Channels(&'a [NgsLogChannel]),
+ Keywords(AhoCorasick),
+ Regex(Regex),
+}
+
+impl<'a> Filter<'a> {
+ /// index 番目（0 始まり）の [[if]] の field に設定された条件式を構築します。
+ /// 1つの条件に複数の項目を設定した場合はすべてを満たす必要があります
+ pub fn compile(
+  index: usize,
+  field: &str,
+  condition: &'a Condition,
+ ) -> Result<Self, NgsLogActionError> {
+  let mut filters = Vec::new();
+  if let Some(ref any) = condition.any {
+   filters.push(Filter::Any(Self::compile_all(
+    index,
+    &format!("{} の any", field),
+    any,
+   )?));
+  }
+  if let Some(ref all) = condition.all {
+   filters.push(Filter::All(Self::compile_all(
+    index,
+    &format!("{} の all", field),
+    all,
+   )?));
+  }
+  if let Some(ref not) = condition.not {
+   filters.push(Filter::Not(Box::new(Self::compile(
+    index,
+    &format!("{} の not", field),
+    not,
+   )?)));
+  }
+  if let Some(ref target) = condition.target {
+   filters.push(Filter::Target(target));
+  }
+  if let Some(ref names) = condition.names {
+   filters.push(Filter::Names(names));
+  }
+  if let Some(ref channels) = condition.channels {
+   filters.push(Filter::Channels(channels));
+  }
+  if let Some(keywords) = compile_keywords(
+   index,
+   &format!("{} の keywords", field),
+   &condition.keywords,
+  )? {
+   filters.push(Filter::Keywords(keywords));
+  }
+  if let Some(regex) = compile_regex(index, &format!("{} の regex", field), &condition.regex)? {
+   filters.push(Filter::Regex(regex));
+  }
+  // 空の条件はすべてのログに一致してしまうため設定の誤りとして扱います
+  Ok(match filters.len() {
+   0 => {
+    return Err(invalid_rule(
+     index,
+     field.to_string(),
+     "条件を1つ以上設定してください",
+    ))
+   }
+   1 => filters.remove(0),
+   _ => Filter::All(filters),
+  })
+ }
+
+ fn compile_all(
+  index: usize,
+  field: &str,
+  conditions: &'a [Condition],
+ ) -> Result<Vec<Self>, NgsLogActionError> {
+  conditions
+   .iter()
+   .enumerate()
+   .map(|(i, condition)| Self::compile(index, &format!("{} #{}", field, i + 1), condition))
+   .collect()
+ }
+
+ /// [[if]] の channels と同じく、チャンネルの無いログ（アイテムなど）は channels の条件を満たします
+ pub fn matches(&self, ngs_log: &NgsLog) -> bool {
+  match self {
+   Filter::Any(filters) => filters.iter().any(|filter| filter.matches(ngs_log)),
+   Filter::All(filters) => filters.iter().all(|filter| filter.matches(ngs_log)),
+   Filter::Not(filter) => !filter.matches(ngs_log),
+   Filter::Target(target) => target.matches(ngs_log),
+   Filter::Names(names) => names.contains(ngs_log.get_name()),
+   Filter::Channels(channels) => ngs_log
+    .get_channel()
+    .is_none_or(|channel| channels.contains(channel)),
+   Filter::Keywords(keywords) => keywords.is_match(ngs_log.get_body_or_item()),
+   Filter::Regex(regex) => regex.is_match(ngs_log.get_body_or_item()),
+  }
+ }
+}
+
+#[cfg(test)]
+mod tests {
+ use super::*;
+ use crate::conf::Conf;
+ use crate::ngs_log::chat_log;
+
+ fn when(conf: &Conf) -> &Condition {
+  conf.r#if.as_ref().unwrap()[0].when.as_ref().unwrap()
+ }
+
+ #[test]
+ fn any_all_and_not_groups_are_evaluated() {
+  let conf: Conf = toml::from_str(
+   r#"
+[[if]]
+when = {any = [
+ {channels = ["GUILD"], names = ["L,A.M."]},
+ {all = [{channels = ["PARTY"]}, {keywords = ["集合"]}], not = {regex = "^/"}},
+]}
+"#,
+  )
+  .unwrap();
+  let filter = Filter::compile(0, "when", when(&conf)).unwrap();
+  assert!(filter.matches(&chat_log(NgsLogChannel::Guild, "L,A.M.", "hi")));
+  assert!(!filter.matches(&chat_log(NgsLogChannel::Guild, "ネクス", "hi")));
+  assert!(filter.matches(&chat_log(NgsLogChannel::Party, "ネクス", "集合です")));
+  assert!(!filter.matches(&chat_log(NgsLogChannel::Party, "ネクス", "/p 集合")));
+  assert!(!filter.matches(&chat_log(NgsLogChannel::Public, "ネクス", "集合です")));
+ }
+
+ #[test]
+ fn channels_pass_logs_without_channel_and_empty_condition_is_rejected() {
+  let conf: Conf = toml::from_str(
+   r#"
+[[if]]
+when = {channels = ["PARTY"]}
+"#,
+  )
+  .unwrap();
+  let filter = Filter::compile(0, "when", when(&conf)).unwrap();
+  let item_log = crate::parser::parse_action_line(
+   "2021-08-19T20:40:17\t243\t[Pickup]\t15161621\tL,A.M.\tN-グラインダー\tNum(1)",
+  )
+  .unwrap()
+  .unwrap();
+  assert!(filter.matches(&item_log));
+
+  let conf: Conf = toml::from_str(
+   r#"
+[[if]]
+when = {any = [{names = ["A"]}, {}]}
+"#,
+  )
+  .unwrap();
+  match Filter::compile(0, "when", when(&conf)) {
+   Err(NgsLogActionError::InvalidRule { field, .. }) => {
+    assert_eq!(field, "when の any #2");
+   }
+   other => panic!("unexpected result: {:?}", other),
+  }
+ }
+
+ #[test]
+ fn invalid_regex_in_nested_condition_reports_its_path() {
+  let conf: Conf = toml::from_str(
+   r#"
+[[if]]
+when = {any = [{names = ["A"]}, {not = {regex = "(unclosed"}}]}
+"#,
+  )
+  .unwrap();
+  match Filter::compile(0, "when", when(&conf)) {
+   Err(NgsLogActionError::InvalidRule { index, field, .. }) => {
+    assert_eq!(index, 1);
+    assert_eq!(field, "when の any #2 の not の regex");
+   }
+   other => panic!("unexpected result: {:?}", other),
+  }
+ }
+
+ #[test]
+ fn hour_ranges_crossing_midnight_belong_to_the_start_date() {
+  let range = HourRange::parse("20:00-02:00").unwrap();
+  let at = |day, hour, minute| {
+   NaiveDate::from_ymd_opt(2021, 8, day)
+    .unwrap()
+    .and_hms_opt(hour, minute, 0)
+    .unwrap()
+  };
+  // 2021-08-20 は金曜日です
+  assert_eq!(
+   range.start_date(&at(20, 20, 0)),
+   NaiveDate::from_ymd_opt(2021, 8, 20)
+  );
+  assert_eq!(
+   range.start_date(&at(21, 1, 59)),
+   NaiveDate::from_ymd_opt(2021, 8, 20)
+  );
+  assert_eq!(range.start_date(&at(21, 2, 0)), None);
+  assert_eq!(range.start_date(&at(20, 19, 59)), None);
+  let daytime = HourRange::parse("12:00-24:00").unwrap();
+  assert_eq!(
+   daytime.start_date(&at(20, 23, 59)),
+   NaiveDate::from_ymd_opt(2021, 8, 20)
+  );
+  assert_eq!(daytime.start_date(&at(20, 11, 59)), None);
+  assert!(HourRange::parse("20:00").is_err());
+
+  let weekdays = [parse_weekday("金").unwrap()];
+  let schedule = |datetime| find_failing_schedule(Some(&[range]), Some(&weekdays), &datetime);
+  assert_eq!(schedule(at(21, 1, 0)), None);
+  assert_eq!(schedule(at(21, 21, 0)), Some("weekdays"));
+  assert_eq!(schedule(at(21, 12, 0)), Some("active_hours"));
+  assert_eq!(
+   find_failing_schedule(None, Some(&[Weekday::Sat]), &at(21, 1, 0)),
+   None
+  );
+ }
+
+ #[test]
+ fn durations_combine_units() {
+  assert_eq!(parse_duration("30s"), Ok(Duration::seconds(30)));
+  assert_eq!(parse_duration("1h30m"), Ok(Duration::minutes(90)));
+  assert!(parse_duration("30").is_err());
+  assert!(parse_duration("0s").is_err());
+  assert!(parse_duration("5 min").is_err());
+  assert!(parse_duration("99999999999999d").is_err());
+  assert!(parse_duration("9223372036854775807s").is_err());
+  assert!(parse_duration("99999999999d99999999999d").is_err());
+ }
+}
@@ -0,0 +1,154 @@
+use crate::error::NgsLogActionError;
+use anyhow::Result;
+use std::{
+ fs,
+ path::{Path, PathBuf},
+};
+
+/// ログフォルダを探す SEGA フォルダ内のクライアントごとのフォルダ名（日本版, グローバル版）
+const CLIENT_DIRECTORY_NAMES: [&str; 3] = [
+ "PHANTASYSTARONLINE2",
+ "PHANTASYSTARONLINE2_NA",
+ "PHANTASYSTARONLINE2_NA_STEAM",
+];
+
+/// Proton の compatdata を探す Steam ライブラリのフォルダ（ホームフォルダからの相対パス）
+const STEAM_DIRECTORIES: [&str; 3] = [
+ ".steam/steam",
+ ".local/share/Steam",
+ ".var/app/com.valvesoftware.Steam/.local/share/Steam",
+];
+
+/// ログフォルダ（log_ngs と log を含むフォルダ）を決定します
+///
+/// `--log-dir`、 conf.toml の `log_roots`、自動検出した候補の順に優先し、
+/// 明示的に指定された場合はその中から log_ngs フォルダのある最初のフォルダを選びます。
+pub fn resolve_log_root(
+ log_dir: Option<&Path>,
+ log_roots: &[PathBuf],
+ home_directory: Option<&Path>,
+) -> Result<PathBuf> {
+ let candidates = match (log_dir, log_roots) {
+  (Some(log_dir), _) => vec![log_dir.to_path_buf()],
+  (None, []) => home_directory.map_or(Vec::new(), discover_log_roots),
+  (None, log_roots) => log_roots
+   .iter()
+   .map(|root| expand_home(root, home_directory))
+   .collect(),
+ };
+ candidates
+  .iter()
+  .find(|candidate| is_log_root(candidate))
+  .cloned()
+  .ok_or_else(|| NgsLogActionError::LogDirectoryNotFound { candidates }.into())
+}
+
+/// 既知のログフォルダの候補を列挙します
+///
+/// Windows のドキュメントフォルダに加えて、 Steam Proton や Wine のプレフィックス内の
+/// ドキュメントフォルダも探します。
+pub fn discover_log_roots(home_directory: &Path) -> Vec<PathBuf> {
+ let mut documents_directories = vec![home_directory.join("Documents")];
+ for steam_directory in &STEAM_DIRECTORIES {
+  let compatdata = home_directory
+   .join(steam_directory)
+   .join("steamapps")
+   .join("compatdata");
+  // compatdata/<appid>/pfx/drive_c/users/steamuser/Documents
+  for prefix in sorted_sub_directories(&compatdata) {
+   documents_directories.push(
+    prefix
+     .join("pfx")
+     .join("drive_c")
+     .join("users")
+     .join("steamuser")
+     .join("Documents"),
+   );
+  }
+ }
+ // ~/.wine/drive_c/users/<user>/Documents
+ let wine_users = home_directory.join(".wine").join("drive_c").join("users");
+ for user in sorted_sub_directories(&wine_users) {
+  documents_directories.push(user.join("Documents"));
+ }
+
+ documents_directories
+  .iter()
+  .flat_map(|documents| {
+   CLIENT_DIRECTORY_NAMES
+    .iter()
+    .map(move |name| documents.join("SEGA").join(name))
+  })
+  .collect()
+}
+
+fn is_log_root(path: &Path) -> bool {
+ path.join("log_ngs").is_dir()
+}
+
+fn sorted_sub_directories(path: &Path) -> Vec<PathBuf> {
+ let mut directories: Vec<_> = match fs::read_dir(path) {
+  Ok(entries) => entries
+   .filter_map(|entry| entry.ok())
+   .map(|entry| entry.path())
+   .filter(|path| path.is_dir())
+   .collect(),
+  Err(_) => Vec::new(),
+ };
+ directories.sort();
+ directories
+}
+
+/// 先頭の `~` をホームフォルダに展開します
+fn expand_home(path: &Path, home_directory: Option<&Path>) -> PathBuf {
+ match (path.strip_prefix("~"), home_directory) {
+  (Ok(rest), Some(home_directory)) => home_directory.join(rest),
+  _ => path.to_path_buf(),
+ }
+}
+
+#[cfg(test)]
+mod tests {
+ use super::*;
+
+ fn temp_home(name: &str) -> PathBuf {
+  let path = std::env::temp_dir().join(format!(
+   "ngs-log-action-log-dir-{}-{}",
+   std::process::id(),
+   name
+  ));
+  let _ = fs::remove_dir_all(&path);
+  path
+ }
+
+ #[test]
+ fn discovers_proton_prefix() {
+  let home = temp_home("proton");
+  let root = home
+   .join(".local/share/Steam/steamapps/compatdata/1056640/pfx/drive_c/users/steamuser")
+   .join("Documents/SEGA/PHANTASYSTARONLINE2_NA_STEAM");
+  fs::create_dir_all(root.join("log_ngs")).unwrap();
+  assert_eq!(resolve_log_root(None, &[], Some(&home)).unwrap(), root);
+  fs::remove_dir_all(&home).unwrap();
+ }
+
+ #[test]
+ fn explicit_roots_take_precedence() {
+  let home = temp_home("explicit");
+  let documents = home.join("Documents/SEGA/PHANTASYSTARONLINE2");
+  let configured = home.join("configured");
+  fs::create_dir_all(documents.join("log_ngs")).unwrap();
+  fs::create_dir_all(configured.join("log_ngs")).unwrap();
+  let log_roots = vec![PathBuf::from("~/missing"), PathBuf::from("~/configured")];
+  assert_eq!(
+   resolve_log_root(None, &log_roots, Some(&home)).unwrap(),
+   configured
+  );
+  assert_eq!(
+   resolve_log_root(Some(&documents), &log_roots, Some(&home)).unwrap(),
+   documents
+  );
+  assert!(resolve_log_root(Some(&home.join("missing")), &[], Some(&home)).is_err());
+  fs::remove_dir_all(&home).unwrap();
+ }
+}
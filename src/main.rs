@@ -1,72 +1,168 @@
 use anyhow::Result;
-use chrono::{
- offset::{Offset, TimeZone},
- DateTime, FixedOffset, Local,
-};
+use chrono::{DateTime, FixedOffset};
+use clap::Parser;
 use dir::home_dir;
-use encoding_rs_io::DecodeReaderBytes;
 use once_cell::sync::Lazy;
 use std::{
- fs::{self, File},
- io::{BufRead, BufReader},
- path::PathBuf,
- process::Command,
- str::FromStr,
+ fs,
+ io::Write,
+ path::{Path, PathBuf},
+ sync::atomic::{AtomicU64, Ordering},
 };
+use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
 
 mod action;
+mod cli;
 mod conf;
 mod error;
+mod filter;
+mod log_dir;
 mod ngs_log;
-
-use conf::{ActionType, Conf, If, Target};
+mod parser;
+mod rate_limit;
+mod replay;
+mod rule;
+mod tailer;
+mod template;
+mod timezone;
+mod trace;
+mod watcher;
+mod window;
+
+use cli::{Args, Command};
+use conf::{ActionType, Conf};
 use error::NgsLogActionError;
-use ngs_log::{ChatLog, ItemCategory, ItemLog, NgsLog, NgsLogChannel};
+use ngs_log::NgsLog;
+use parser::{ChatRecordReader, ParseError};
+use rule::Rule;
+use tailer::{LogCursor, LogTailer, TailedLine};
+use template::Variables;
+use trace::Evaluation;
+use watcher::LogWatcher;
 
 static CONF: Lazy<Conf> = Lazy::new(|| {
  let conf_str = fs::read_to_string("conf.toml").unwrap();
- let conf = toml::from_str(&conf_str).unwrap();
- conf
+
+ toml::from_str(&conf_str).unwrap()
 });
 
+static ARGS: Lazy<Args> = Lazy::new(Args::parse);
+
+/// 解析できずに読み飛ばしたログの行数
+static SKIPPED_LINE_COUNT: AtomicU64 = AtomicU64::new(0);
+
 #[tokio::main]
 async fn main() -> Result<()> {
- let mut last_log_datetime = now();
-
+ timezone::initialize(CONF.get_log_timezone()?);
+ rule::initialize(&CONF)?;
  action::initialize().await;
- let polling_sleep = 1.0 / CONF.get_polling_rate();
+ if ARGS.dry_run {
+  action::enable_dry_run();
+ }
+ if ARGS.explain || CONF.get_trace() {
+  trace::enable();
+ }
+ match ARGS.command {
+  Some(Command::Replay(ref replay_args)) => {
+   // 再生するログが指定されている場合はログフォルダが見つからなくても構いません
+   let log_root = match replay_args.paths.is_empty() {
+    true => Some(resolve_log_root()?),
+    false => None,
+   };
+   replay::run(replay_args, log_root).await?;
+   print_shutdown_message();
+   Ok(())
+  }
+  None => follow_logs().await,
+ }
+}
+
+fn resolve_log_root() -> Result<PathBuf> {
+ log_dir::resolve_log_root(
+  ARGS.log_dir.as_deref(),
+  &CONF.get_log_roots(),
+  home_dir().as_deref(),
+ )
+}
+
+/// ログファイルを追いかけて、追記されたログに [[if]] の設定を適用し続けます
+async fn follow_logs() -> Result<()> {
+ let mut log_tailers = LogTailers::initialize(resolve_log_root()?).await?;
+
+ let polling_interval = tokio::time::Duration::from_secs_f64(1.0 / CONF.get_polling_rate());
+ let mut log_watcher = if CONF.get_watch() {
+  let log_root = &log_tailers.log_root;
+  let directories = [log_root.join("log_ngs"), log_root.join("log")];
+  let directories: Vec<_> = directories.iter().map(PathBuf::as_path).collect();
+  LogWatcher::watch(&directories, polling_interval).unwrap_or_else(|e| {
+   println!(
+    "[System]{}ファイルの変更通知を利用できないためポーリングで動作します: {}",
+    CONF.get_column_separator(),
+    e
+   );
+   LogWatcher::polling(polling_interval)
+  })
+ } else {
+  LogWatcher::polling(polling_interval)
+ };
 
  println!(
   "[System]{}NGS Log Action {} 起動 {}",
   CONF.get_column_separator(),
   env!("CARGO_PKG_VERSION"),
-  format_datetime(&last_log_datetime)
+  format_datetime(&now())
  );
+ println!(
+  "[System]{}ログフォルダ: {}",
+  CONF.get_column_separator(),
+  log_tailers.log_root.display()
+ );
+ println!(
+  "[System]{}ログの読み込み: {}",
+  CONF.get_column_separator(),
+  if log_watcher.is_watching() {
+   "ファイルの変更通知"
+  } else {
+   "ポーリング"
+  }
+ );
+ if action::is_dry_run() {
+  println!(
+   "[System]{}dry-run: sound, command, get, post は実行しません",
+   CONF.get_column_separator()
+  );
+ }
 
  loop {
   {
-   let ngs_logs = get_new_logs(last_log_datetime).await?;
-   if !ngs_logs.is_empty() {
-    for ngs_log in &ngs_logs {
-     apply_ngs_log_actions(&ngs_log).await?;
-    }
-    last_log_datetime = ngs_logs
-     .last()
-     .ok_or(NgsLogActionError::ErrorCode(300))?
-     .get_datetime()
-     .clone();
+   let ngs_logs = get_new_logs(&mut log_tailers).await?;
+   for ngs_log in &ngs_logs {
+    apply_ngs_log_actions(ngs_log).await?;
    }
   }
 
-  tokio::time::sleep(tokio::time::Duration::from_secs_f64(polling_sleep)).await;
+  tokio::select! {
+   _ = log_watcher.wait() => (),
+   _ = tokio::signal::ctrl_c() => break,
+  }
  }
 
- // Ok(())
+ print_shutdown_message();
+
+ Ok(())
+}
+
+fn print_shutdown_message() {
+ println!(
+  "[System]{}NGS Log Action 終了 {} (解析できずに読み飛ばした行: {})",
+  CONF.get_column_separator(),
+  format_datetime(&now()),
+  SKIPPED_LINE_COUNT.load(Ordering::Relaxed)
+ );
 }
 
 fn now() -> DateTime<FixedOffset> {
- let tz_offset = Local.timestamp(0, 0).offset().fix();
- Local::now().with_timezone(&tz_offset)
+ timezone::now()
 }
 
 fn format_datetime(datetime: &DateTime<FixedOffset>) -> String {
@@ -81,453 +177,595 @@ fn format_datetime(datetime: &DateTime<FixedOffset>) -> String {
 }
 
 async fn apply_ngs_log_actions(ngs_log: &NgsLog) -> Result<()> {
+ action::track_meseta(ngs_log).await;
  let mut finished_actions = Vec::new();
- if let Some(r#if) = &CONF.r#if {
-  for r#if in r#if {
-   apply_log_action(r#if, ngs_log, &mut finished_actions).await?;
+ let trace = trace::is_enabled();
+ if trace {
+  trace::print_log(ngs_log)?;
+ }
+ for (index, rule) in rule::rules().iter().enumerate() {
+  let evaluation = apply_log_action(rule, ngs_log, &mut finished_actions).await?;
+  if trace {
+   trace::print_evaluation(index, &evaluation)?;
   }
  }
  Ok(())
 }
 
 async fn apply_log_action(
- r#if: &If,
+ rule: &Rule<'_>,
  ngs_log: &NgsLog,
  finished_actions: &mut Vec<ActionType>,
-) -> Result<()> {
- // filters
- if let Some(ref target) = r#if.target {
-  match ngs_log {
-   NgsLog::ChatLog(_) if target.ne(&Target::Chat) => return Ok(()),
-   NgsLog::ItemLog(_) if target.ne(&Target::Item) => return Ok(()),
-   _ => {}
-  }
+) -> Result<Evaluation> {
+ if let Some(condition) = rule.find_failing_condition(ngs_log) {
+  return Ok(Evaluation::Rejected(condition));
  }
- if let Some(ref channels) = r#if.channels {
-  if let Some(channel) = ngs_log.get_channel() {
-   if !channels.contains(channel) {
-    return Ok(());
-   }
-  }
+ if rule.r#if.item_counts.is_some()
+  && !rule.matches_item_counts(action::ITEM_COUNTER.lock().await.iter())
+ {
+  return Ok(Evaluation::Rejected("item_counts"));
  }
- if let Some(ref names) = r#if.names {
-  let name = ngs_log.get_name();
-  if !names.contains(name) {
-   return Ok(());
-  }
+ let mut variables = Variables::from_log(ngs_log);
+ rule.insert_captures(ngs_log, &mut variables);
+ match rule.record_sequence(ngs_log) {
+  Some(Some(elapsed)) => variables.insert_elapsed(elapsed),
+  Some(None) => return Ok(Evaluation::Rejected("sequence")),
+  None => {}
  }
- if let Some(ref keywords) = r#if.keywords {
-  if !keywords
-   .iter()
-   .any(|keyword| ngs_log.get_body_or_item().find(keyword).is_some())
-  {
-   return Ok(());
-  }
+ if let Some(condition) = rule.record_burst(ngs_log) {
+  return Ok(Evaluation::Rejected(condition));
  }
- if let Some(ref regex) = r#if.regex {
-  let regex = regex::Regex::new(regex)?;
-  if !regex.is_match(&ngs_log.get_body_or_item()) {
-   return Ok(());
-  }
+ if let Some((limit, count)) = rule.limit(ngs_log) {
+  return Ok(Evaluation::Suppressed { limit, count });
  }
 
- // ignore- series
- if let Some(ref ignore_names) = r#if.ignore_names {
-  if ignore_names.contains(&ngs_log.get_name()) {
-   return Ok(());
-  }
- }
- if let Some(ref ignore_keywords) = r#if.ignore_keywords {
-  if ignore_keywords
-   .iter()
-   .any(|ignore_keyword| ngs_log.get_body_or_item().find(ignore_keyword).is_some())
-  {
-   return Ok(());
-  }
- }
- if let Some(ref ignore_regex) = r#if.ignore_regex {
-  let ignore_regex = regex::Regex::new(ignore_regex)?;
-  if ignore_regex.is_match(&ngs_log.get_body_or_item()) {
-   return Ok(());
+ let skipped_actions = match rule.r#if.action {
+  Some(ref action) => {
+   action::do_action(
+    action,
+    ngs_log,
+    &variables,
+    finished_actions,
+    rule.r#if.dry_run == Some(true),
+   )
+   .await?
   }
- }
+  None => Vec::new(),
+ };
+ Ok(Evaluation::Fired { skipped_actions })
+}
 
- if let Some(ref item_counts) = r#if.item_counts {
-  let item_counter = action::ITEM_COUNTER.lock().await.clone();
-  for (i, c) in item_counter.iter() {
-   for p in item_counts {
-    if let Some(every) = p.every {
-     if c.prev / every >= c.current / every {
-      continue;
-     }
-    }
-    if let Some(ref ks) = p.keywords {
-     if !ks.iter().any(|k| i.contains(k)) {
-      continue;
-     }
-    }
-    if let Some(ref re) = p.regex {
-     let re = regex::Regex::new(re)?;
-     if !re.is_match(i) {
-      continue;
-     }
-    }
-    if let Some(ref action) = r#if.action {
-     action::do_action(action, &ngs_log, finished_actions).await?;
-    }
-   }
-  }
- } else if let Some(ref action) = r#if.action {
-  action::do_action(action, &ngs_log, finished_actions).await?;
+/// https://github.com/LAM-SHIP01-JP-PSO2NGS/ngs-log-action/issues/1
+#[cfg(windows)]
+async fn last_modified_fix(path: &Path) -> Result<()> {
+ if let Some(path_str) = path.to_str() {
+  let _output = std::process::Command::new("cmd")
+   .args(&["/c", "dir", "/A", "/R", "/Q", path_str])
+   .output()?;
  }
  Ok(())
 }
 
-fn parse_datetime(datetime_string: &str) -> Result<DateTime<FixedOffset>> {
- let tz_offset = Local.timestamp(0, 0).offset().fix();
- let datetime_string = format!("{}{:?}", datetime_string, &tz_offset);
- let datetime = DateTime::parse_from_rfc3339(&datetime_string)?;
- Ok(datetime)
+/// Windows 以外では更新日時は書き込みの度に更新されます
+#[cfg(not(windows))]
+async fn last_modified_fix(_path: &Path) -> Result<()> {
+ Ok(())
 }
 
-async fn get_ngs_user_directory_path() -> Result<PathBuf> {
- let home_directory = home_dir().ok_or(NgsLogActionError::ErrorCode(1))?;
- let ngs_user_directory_path = home_directory
-  .join("Documents")
-  .join("SEGA")
-  .join("PHANTASYSTARONLINE2");
- Ok(ngs_user_directory_path)
+/// ログの種別
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LogKind {
+ Chat,
+ Action,
+ Reward,
+ SymbolChat,
+ Scratch,
+ StarGem,
 }
 
-async fn get_ngs_logs_directory_path() -> Result<PathBuf> {
- let ngs_logs_directory_path = get_ngs_user_directory_path().await?.join("log_ngs");
- Ok(ngs_logs_directory_path)
-}
+impl LogKind {
+ const ALL: [LogKind; 6] = [
+  LogKind::Chat,
+  LogKind::Action,
+  LogKind::Reward,
+  LogKind::SymbolChat,
+  LogKind::Scratch,
+  LogKind::StarGem,
+ ];
+
+ fn file_name_prefix(self) -> &'static str {
+  match self {
+   LogKind::Chat => "ChatLog",
+   LogKind::Action => "ActionLog",
+   LogKind::Reward => "RewardLog",
+   LogKind::SymbolChat => "SymbolChatLog",
+   LogKind::Scratch => "ScratchLog",
+   LogKind::StarGem => "StarGemLog",
+  }
+ }
 
-async fn get_pso2_logs_directory_path() -> Result<PathBuf> {
- let ngs_logs_directory_path = get_ngs_user_directory_path().await?.join("log");
- Ok(ngs_logs_directory_path)
+ /// ファイル名からログの種別を判定します
+ fn from_path(path: &Path) -> Option<Self> {
+  let file_name = path.file_name()?.to_string_lossy();
+  LogKind::ALL
+   .iter()
+   .copied()
+   .find(|kind| file_name.starts_with(kind.file_name_prefix()))
+ }
 }
 
-/// https://github.com/LAM-SHIP01-JP-PSO2NGS/ngs-log-action/issues/1
-async fn last_modified_fix(path: &PathBuf) -> Result<()> {
- if let Some(path_str) = path.to_str() {
-  let _output = Command::new("cmd")
-   .args(&["/c", "dir", "/A", "/R", "/Q", path_str])
-   .output()
-   .unwrap();
+/// ログフォルダ内の log_ngs と log のそれぞれで、種別ごとに最新のログファイルを返します
+///
+/// ファイル名に日付が含まれるため ( 例: ChatLog20210819_00.txt ) 更新日時ではなくファイル名で比較します。
+/// return Result<Vec<( 種別, フォルダ, ファイル )>>
+async fn get_latest_log_file_paths(log_root: &Path) -> Result<Vec<(LogKind, PathBuf, PathBuf)>> {
+ let mut latest_log_file_paths = Vec::new();
+ for directory in [log_root.join("log_ngs"), log_root.join("log")] {
+  last_modified_fix(&directory).await?;
+  let mut file_names: Vec<_> = match fs::read_dir(&directory) {
+   Ok(entries) => entries
+    .filter_map(|entry| entry.ok())
+    .map(|entry| entry.file_name().to_string_lossy().to_string())
+    .collect(),
+   // PSO2 を遊んでいない環境では log フォルダが無い場合があります
+   Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+   Err(e) => return Err(e.into()),
+  };
+  file_names.sort();
+  for kind in LogKind::ALL {
+   let latest = file_names
+    .iter()
+    .rev()
+    .find(|file_name| file_name.starts_with(kind.file_name_prefix()));
+   if let Some(latest) = latest {
+    latest_log_file_paths.push((kind, directory.clone(), directory.join(latest)));
+   }
+  }
  }
- Ok(())
+ Ok(latest_log_file_paths)
 }
 
-/// return Result<( Chat, Action, Reward )>
-async fn get_latest_log_file_paths() -> Result<(Option<PathBuf>, Option<PathBuf>, Option<PathBuf>)>
-{
- let ngs_logs_path = get_ngs_logs_directory_path().await?;
- last_modified_fix(&ngs_logs_path).await?;
- let ngs_directory_entries = fs::read_dir(ngs_logs_path)?;
- let mut pso2ngs_directory_entries: Vec<_> = ngs_directory_entries.map(|a| a.unwrap()).collect();
-
- let pso2_logs_path = get_pso2_logs_directory_path().await?;
- last_modified_fix(&pso2_logs_path).await?;
- let pso2_directory_entries = fs::read_dir(pso2_logs_path)?;
- let mut pso2_directory_entries: Vec<_> = pso2_directory_entries.map(|a| a.unwrap()).collect();
-
- pso2ngs_directory_entries.append(&mut pso2_directory_entries);
-
- pso2ngs_directory_entries.sort_by(|a, b| {
-  let a = a.metadata().unwrap().modified().unwrap();
-  let b = b.metadata().unwrap().modified().unwrap();
-  b.cmp(&a)
- });
+/// 追いかけているログファイル
+struct ActiveLogFile {
+ kind: LogKind,
+ /// ログファイルのあるフォルダ ( log_ngs または log )
+ directory: PathBuf,
+ tailer: LogTailer,
+ /// 複数行のチャットをポーリングをまたいで組み立てるリーダー（チャットログのみ使用）
+ chat_records: ChatRecordReader,
+}
 
- let chat = pso2ngs_directory_entries
-  .iter()
-  .find(|a| a.file_name().to_string_lossy().starts_with("ChatLog"))
-  .map(|e| e.path());
+impl ActiveLogFile {
+ fn new(kind: LogKind, directory: PathBuf, tailer: LogTailer) -> Self {
+  Self {
+   kind,
+   directory,
+   tailer,
+   chat_records: ChatRecordReader::default(),
+  }
+ }
 
- let action = pso2ngs_directory_entries
-  .iter()
-  .find(|a| a.file_name().to_string_lossy().starts_with("ActionLog"))
-  .map(|e| e.path());
+ /// 追記されたログを読み込みます
+ async fn read_new_logs(&mut self) -> Result<Vec<NgsLog>> {
+  let lines = self.tailer.read_lines()?;
+  self.parse_lines(lines).await
+ }
 
- let reward = pso2ngs_directory_entries
-  .iter()
-  .find(|a| a.file_name().to_string_lossy().starts_with("RewardLog"))
-  .map(|e| e.path());
+ /// ファイルの最後まで読み込みます。改行で終わっていない最後の行も読み込みます
+ async fn read_remaining_logs(&mut self) -> Result<Vec<NgsLog>> {
+  let lines = self.tailer.read_to_end()?;
+  self.parse_lines(lines).await
+ }
 
- Ok((chat, action, reward))
+ async fn parse_lines(&mut self, lines: Vec<TailedLine>) -> Result<Vec<NgsLog>> {
+  let path = self.tailer.path().to_path_buf();
+  let cursor = self.tailer.cursor_mut();
+  match self.kind {
+   LogKind::Chat => get_new_chat_logs(&path, lines, cursor, &mut self.chat_records).await,
+   LogKind::Action => get_new_action_logs(&path, lines, cursor).await,
+   LogKind::Reward => get_new_reward_logs(&path, lines, cursor).await,
+   LogKind::SymbolChat => get_new_line_logs(&path, lines, cursor, |l| {
+    parser::parse_symbol_chat_line(l).map(Some)
+   }),
+   LogKind::Scratch => get_new_line_logs(&path, lines, cursor, |l| {
+    parser::parse_scratch_line(l).map(Some)
+   }),
+   LogKind::StarGem => get_new_line_logs(&path, lines, cursor, |l| {
+    parser::parse_star_gem_line(l).map(Some)
+   }),
+  }
+ }
 }
 
-fn create_reader_from_path(path: PathBuf) -> Result<BufReader<DecodeReaderBytes<File, Vec<u8>>>> {
- let file = fs::OpenOptions::new()
-  .read(true)
-  .write(false)
-  .create(false)
-  .open(path)?;
+/// log_ngs と log のフォルダごと、ログ種別ごとに最新のログファイルを追いかけるテイラー
+///
+/// NGS と PSO2 のブロックを移動するとそれぞれのフォルダのログに交互に書き込まれるため、
+/// 両方のフォルダのログファイルを同時に追いかけます。
+struct LogTailers {
+ /// log_ngs と log を含むログフォルダ
+ log_root: PathBuf,
+ files: Vec<ActiveLogFile>,
+}
 
- let reader = DecodeReaderBytes::new(file);
- let reader = BufReader::new(reader);
- Ok(reader)
+impl LogTailers {
+ /// 起動時点で存在するログは過去ログとして読み飛ばします
+ async fn initialize(log_root: PathBuf) -> Result<Self> {
+  let mut files = Vec::new();
+  for (kind, directory, path) in get_latest_log_file_paths(&log_root).await? {
+   files.push(ActiveLogFile::new(
+    kind,
+    directory,
+    LogTailer::new_at_end(path)?,
+   ));
+  }
+  Ok(Self { log_root, files })
+ }
+
+ /// 新しいログファイルに切り替わったフォルダ・種別のファイルを差し替えます
+ ///
+ /// return 差し替えられた以前のファイル（読み残しを読み切ってから破棄します）
+ async fn follow_latest_log_files(&mut self) -> Result<Vec<ActiveLogFile>> {
+  let mut rotated_files = Vec::new();
+  for (kind, directory, path) in get_latest_log_file_paths(&self.log_root).await? {
+   let current = self
+    .files
+    .iter()
+    .position(|f| f.kind == kind && f.directory == directory);
+   match current {
+    Some(i) if self.files[i].tailer.path() == path => continue,
+    Some(i) => rotated_files.push(self.files.remove(i)),
+    None => {}
+   }
+   // 起動後に作られたファイルは先頭から読み込みます
+   self
+    .files
+    .push(ActiveLogFile::new(kind, directory, LogTailer::new(path)));
+  }
+  Ok(rotated_files)
+ }
 }
 
-async fn get_latest_log_readers() -> Result<(
- Option<BufReader<DecodeReaderBytes<File, Vec<u8>>>>,
- Option<BufReader<DecodeReaderBytes<File, Vec<u8>>>>,
- Option<BufReader<DecodeReaderBytes<File, Vec<u8>>>>,
-)> {
- let (chat, action, reward) = get_latest_log_file_paths().await?;
- let chat = chat.map(|p| create_reader_from_path(p).unwrap());
- let action = action.map(|p| create_reader_from_path(p).unwrap());
- let reward = reward.map(|p| create_reader_from_path(p).unwrap());
- Ok((chat, action, reward))
+/// 解析できない行を表示して読み飛ばします
+fn skip_malformed_line(
+ path: &Path,
+ line_number: u64,
+ raw_line: &str,
+ error: ParseError,
+) -> Result<()> {
+ SKIPPED_LINE_COUNT.fetch_add(1, Ordering::Relaxed);
+ let error = NgsLogActionError::MalformedLine {
+  file: path.to_path_buf(),
+  line_number,
+  column: error.column(),
+  raw_line: raw_line.to_string(),
+  source: error,
+ };
+ let mut stdout = StandardStream::stdout(ColorChoice::Always);
+ let color = Some(Color::Ansi256(CONF.get_color_ansi256_system()));
+ stdout.set_color(ColorSpec::new().set_fg(color))?;
+ writeln!(
+  &mut stdout,
+  "[System]{}解析できない行を読み飛ばしました: {}",
+  CONF.get_column_separator(),
+  error
+ )?;
+ Ok(())
 }
 
-fn unescape_double_quote(s: &str) -> String {
- s.replacen(r#""""#, r#"""#, usize::MAX)
+/// 夏時間の終わりで同じ時刻が2回ある場合に、カーソルより前に戻らない方の日時に解決し直します
+fn resolve_repeated_datetime(cursor: &Option<LogCursor>, ngs_log: &mut NgsLog) {
+ if let Some(cursor) = cursor {
+  let datetime = ngs_log.get_datetime_mut();
+  *datetime = timezone::log_timezone().localize(datetime.naive_local(), Some(&cursor.datetime));
+ }
 }
 
-fn pre_unescape_double_quote(s: &str) -> String {
- s.replacen(r#""""#, "\t", usize::MAX)
+/// カーソルより後ろの未配信のログか判定します
+fn is_new_log(cursor: &Option<LogCursor>, ngs_log: &NgsLog, position: u64) -> bool {
+ cursor
+  .as_ref()
+  .is_none_or(|c| c.is_behind(ngs_log.get_datetime(), ngs_log.get_log_id(), position))
 }
 
-fn finish_unescape_double_quote(s: &str) -> String {
- s.replacen("\t", r#"""#, usize::MAX)
+fn advance_cursor(cursor: &mut Option<LogCursor>, ngs_log: &NgsLog, position: u64) {
+ *cursor = Some(LogCursor {
+  datetime: *ngs_log.get_datetime(),
+  log_id: ngs_log.get_log_id(),
+  position,
+ });
 }
 
 async fn get_new_chat_logs(
- reader: Option<BufReader<DecodeReaderBytes<File, Vec<u8>>>>,
- last_datetime: &DateTime<FixedOffset>,
+ path: &Path,
+ lines: Vec<TailedLine>,
+ cursor: &mut Option<LogCursor>,
+ record_reader: &mut ChatRecordReader,
 ) -> Result<Vec<NgsLog>> {
  let mut ngs_logs = Vec::new();
 
- if let Some(chat) = reader {
-  let lines = chat.lines();
-
-  for line in lines {
-   if let Ok(line) = line {
-    match extract_datetime(&line) {
-     // 過去ログ
-     Ok((datetime, _)) if &datetime <= last_datetime => (),
-     // 新規ログ
-     Ok((datetime, tail)) if &datetime > last_datetime => {
-      let mut tail = tail.split("\t");
-      let log_id = tail.next().unwrap().parse()?;
-      let channel = NgsLogChannel::from_str(tail.next().unwrap()).unwrap();
-      let player_id = tail.next().unwrap().parse()?;
-      let name = tail.next().unwrap().to_string();
-      let mut body = unescape_double_quote(tail.next().unwrap());
-      // 複数行の最初の行
-      if body == r#"""# {
-       body = "\n".to_string();
-      }
-      if body.starts_with(r#"""#) && body.char_indices().nth(1).unwrap().1 != '"' {
-       body = body[1..].to_string();
-      }
-      ngs_logs.push(NgsLog::ChatLog(ChatLog {
-       datetime,
-       log_id,
-       channel,
-       player_id,
-       name,
-       body,
-      }))
-     }
-     // 新規ログまたは新規ログの2行目以降
-     _ => {
-      if let Some(last_log) = ngs_logs.last_mut() {
-       let line = pre_unescape_double_quote(&line);
-       // 新規ログの2行目以降
-       if line.chars().last() == Some('"') {
-        // 複数行の最後の行( " で終端 )
-        let line = finish_unescape_double_quote(&line[..line.len() - 1]);
-        last_log.append_body(&line);
-        // (*last_log).body = format!("{}\n{}", last_log.body, line)
-       } else {
-        // 複数行の途中の行
-        last_log.append_body(&finish_unescape_double_quote(&line));
-       }
-      } else {
-       // 前回検出した最後のログが複数行だった場合
-       ()
-      }
-     }
-    }
+ for line in lines {
+  // ファイルの先頭から読み直す場合は読みかけのレコードを破棄します
+  if line.line_number == 1 {
+   record_reader.clear();
+  }
+  // 複数行のチャットはレコードが完結するまで次の行（次回のポーリング）を待ちます
+  let (record, ngs_log) =
+   match parser::parse_chat_line(record_reader, line.position, line.line_number, &line.text) {
+    Some(parsed) => parsed,
+    None => continue,
+   };
+  let mut ngs_log = match ngs_log {
+   Ok(ngs_log) => ngs_log,
+   Err(error) => {
+    skip_malformed_line(path, record.line_number, &record.raw, error)?;
+    continue;
    }
+  };
+  resolve_repeated_datetime(cursor, &mut ngs_log);
+  // 配信済みのログ
+  if !is_new_log(cursor, &ngs_log, record.position) {
+   continue;
   }
+  advance_cursor(cursor, &ngs_log, record.position);
+  ngs_logs.push(ngs_log);
  }
 
  Ok(ngs_logs)
 }
 
-async fn get_new_action_logs(
- reader: Option<BufReader<DecodeReaderBytes<File, Vec<u8>>>>,
- last_datetime: &DateTime<FixedOffset>,
+/// 1行で完結するログを解析し、未配信のログを返します。 parse_line が None を返す行は扱いません
+fn get_new_line_logs(
+ path: &Path,
+ lines: Vec<TailedLine>,
+ cursor: &mut Option<LogCursor>,
+ parse_line: impl Fn(&str) -> Result<Option<NgsLog>, ParseError>,
 ) -> Result<Vec<NgsLog>> {
  let mut ngs_logs = Vec::new();
- if let Some(action) = reader {
-  let lines = action.lines();
-
-  for line in lines {
-   if let Ok(line) = line {
-    match extract_datetime(&line) {
-     // 過去ログ
-     Ok((datetime, _)) if &datetime <= last_datetime => (),
-     // 新規ログ
-     Ok((datetime, tail)) if &datetime > last_datetime => {
-      let mut tail = tail.split("\t");
-      let log_id = tail.next().unwrap().parse()?;
-      let category_string = tail.next().unwrap();
-      match category_string {
-       "[Pickup]" => {
-        let category = ItemCategory::Pickup;
-        let player_id = tail.next().unwrap().parse()?;
-        let name = tail.next().unwrap().to_string();
-        let mut item = tail.next().unwrap().to_string();
-        let count = match item.is_empty() {
-         true => {
-          // 例: 2021-08-19T20:40:56	250	[Pickup]	15161621	L,A.M.		Meseta(12)	CurrentMeseta(26029094)
-          let buffer = tail.next().unwrap().to_string();
-          if buffer.starts_with("Meseta") {
-           item = "Meseta".to_string();
-           buffer[7..buffer.len() - 1].parse().unwrap()
-          } else {
-           panic!();
-          }
-         }
-         false => {
-          if let Some(buffer) = tail.next() {
-           let buffer = buffer.to_string();
-           if let Some(num_begin) = buffer.find("Num(") {
-            // 例: 2021-08-19T20:40:17	243	[Pickup]	15161621	L,A.M.	N-グラインダー	Num(1)
-            buffer[num_begin + 4..buffer.len() - 1].parse().unwrap()
-           } else {
-            // 例: 2021-08-19T20:55:51	406	[Pickup]	15161621	L,A.M.	ツヴィアダガー	attr:NONE(0)
-            //     2021-09-03T10:20:08	477	[Pickup]	15161621	L,A.M.	サプライズナックル	Level(13)
-            1
-           }
-          } else {
-           // 例: 2021-08-19T20:40:56	249	[Pickup]	15161621	L,A.M.	ツヴィアアーマ
-           1
-          }
-         }
-        };
-        ngs_logs.push(NgsLog::ItemLog(ItemLog {
-         datetime,
-         log_id,
-         category,
-         player_id,
-         name,
-         item,
-         count,
-        }))
-       }
-       _ => {}
-      };
-     }
-     _ => {}
-    }
+ for line in lines {
+  let ngs_log = match parse_line(&line.text) {
+   Ok(ngs_log) => ngs_log,
+   Err(error) => {
+    skip_malformed_line(path, line.line_number, &line.text, error)?;
+    continue;
+   }
+  };
+  if let Some(mut ngs_log) = ngs_log {
+   resolve_repeated_datetime(cursor, &mut ngs_log);
+   // 配信済みのログ
+   if !is_new_log(cursor, &ngs_log, line.position) {
+    continue;
    }
+   advance_cursor(cursor, &ngs_log, line.position);
+   ngs_logs.push(ngs_log);
   }
  }
 
  Ok(ngs_logs)
 }
 
-async fn get_new_reward_logs(
- reader: Option<BufReader<DecodeReaderBytes<File, Vec<u8>>>>,
- last_datetime: &DateTime<FixedOffset>,
+async fn get_new_action_logs(
+ path: &Path,
+ lines: Vec<TailedLine>,
+ cursor: &mut Option<LogCursor>,
 ) -> Result<Vec<NgsLog>> {
- let mut ngs_logs = Vec::new();
- if let Some(action) = reader {
-  let lines = action.lines();
-
-  for line in lines {
-   if let Ok(line) = line {
-    match extract_datetime(&line) {
-     // 過去ログ
-     Ok((datetime, _)) if &datetime <= last_datetime => (),
-     // 新規ログ
-     Ok((datetime, tail)) if &datetime > last_datetime => {
-      let mut tail = tail.split("\t");
-      let category = ItemCategory::Reward;
-      let log_id = tail.next().unwrap().parse().unwrap();
-      let _unknown = tail.next().unwrap();
-      let player_id = 0; // TODO
-      let name = tail.next().unwrap().to_string();
-      let switcher = tail.next().unwrap();
-      match switcher {
-       "Meseta" => {
-        let item = "Meseta".to_string();
-        let count = tail.next().unwrap();
-        let count_begin = count.find("(");
-        let count_end = count.find(")");
-        if count_begin.is_some() && count_end.is_some() {
-         let count = (&count[count_begin.unwrap() + 1..count_end.unwrap()])
-          .parse()
-          .unwrap();
-         ngs_logs.push(NgsLog::ItemLog(ItemLog {
-          datetime,
-          log_id,
-          category,
-          player_id,
-          name,
-          item,
-          count,
-         }))
-        }
-       }
-       "Backpack" => {
-        let item = tail.next().unwrap().to_string();
-        let count = tail.next().unwrap();
-        let count_begin = count.find("(");
-        let count_end = count.find(")");
-        if count_begin.is_some() && count_end.is_some() {
-         let count = (&count[count_begin.unwrap() + 1..count_end.unwrap()])
-          .parse()
-          .unwrap();
-         ngs_logs.push(NgsLog::ItemLog(ItemLog {
-          datetime,
-          log_id,
-          category,
-          player_id,
-          name,
-          item,
-          count,
-         }))
-        }
-       }
-       _ => {}
-      }
-     }
-     _ => {}
-    }
-   }
-  }
- }
+ get_new_line_logs(path, lines, cursor, parser::parse_action_line)
+}
 
- Ok(ngs_logs)
+async fn get_new_reward_logs(
+ path: &Path,
+ lines: Vec<TailedLine>,
+ cursor: &mut Option<LogCursor>,
+) -> Result<Vec<NgsLog>> {
+ get_new_line_logs(path, lines, cursor, |line| {
+  parser::parse_reward_line(line).map(Some)
+ })
 }
 
-async fn get_new_logs(last_datetime: DateTime<FixedOffset>) -> Result<Vec<NgsLog>> {
+async fn get_new_logs(log_tailers: &mut LogTailers) -> Result<Vec<NgsLog>> {
  let mut ngs_logs = Vec::new();
 
- let (chat, action, reward) = get_latest_log_readers().await?;
- let mut chat = get_new_chat_logs(chat, &last_datetime).await?;
- let mut action = get_new_action_logs(action, &last_datetime).await?;
- let mut reward = get_new_reward_logs(reward, &last_datetime).await?;
- ngs_logs.append(&mut chat);
- ngs_logs.append(&mut action);
- ngs_logs.append(&mut reward);
+ // 日付が変わって新しいファイルに切り替わった場合も、以前のファイルの読み残しを先に読み切ります
+ for mut rotated_file in log_tailers.follow_latest_log_files().await? {
+  ngs_logs.append(&mut rotated_file.read_new_logs().await?);
+ }
+ for file in &mut log_tailers.files {
+  ngs_logs.append(&mut file.read_new_logs().await?);
+ }
  ngs_logs.sort_by(|a, b| a.get_datetime().cmp(b.get_datetime()));
 
  Ok(ngs_logs)
 }
 
-fn extract_datetime(line: &str) -> Result<(DateTime<FixedOffset>, String)> {
- let (first_column, tail) = line
-  .split_once("\t")
-  .ok_or(NgsLogActionError::ErrorCode(200))?;
- let datetime = parse_datetime(first_column)?;
- Ok((datetime, tail.to_string()))
+#[cfg(test)]
+mod tests {
+ use super::*;
+
+ fn tailed_line(position: u64, text: &str) -> TailedLine {
+  TailedLine {
+   position,
+   line_number: 0,
+   text: text.to_string(),
+  }
+ }
+
+ #[tokio::test]
+ async fn same_second_logs_in_different_polls_are_not_dropped() {
+  let mut chat_cursor = None;
+  let mut chat_records = ChatRecordReader::default();
+  let mut action_cursor = None;
+
+  let first = vec![tailed_line(
+   2,
+   "2021-08-19T20:40:56\t100\tGUILD\t15161621\tL,A.M.\tfirst",
+  )];
+  let logs = get_new_chat_logs(
+   Path::new("ChatLog.txt"),
+   first,
+   &mut chat_cursor,
+   &mut chat_records,
+  )
+  .await
+  .unwrap();
+  assert_eq!(logs.len(), 1);
+
+  let second = vec![tailed_line(
+   120,
+   "2021-08-19T20:40:56\t101\tGUILD\t15161621\tL,A.M.\tsecond",
+  )];
+  let logs = get_new_chat_logs(
+   Path::new("ChatLog.txt"),
+   second,
+   &mut chat_cursor,
+   &mut chat_records,
+  )
+  .await
+  .unwrap();
+  assert_eq!(logs.len(), 1);
+  assert_eq!(logs[0].get_body_or_item(), "second");
+
+  let pickup = vec![tailed_line(
+   2,
+   "2021-08-19T20:40:56\t243\t[Pickup]\t15161621\tL,A.M.\tN-グラインダー\tNum(1)",
+  )];
+  let logs = get_new_action_logs(Path::new("ActionLog.txt"), pickup, &mut action_cursor)
+   .await
+   .unwrap();
+  assert_eq!(logs.len(), 1);
+ }
+
+ #[tokio::test]
+ async fn delivered_logs_are_skipped_when_a_file_is_reread() {
+  let mut cursor = None;
+  let mut chat_records = ChatRecordReader::default();
+  let lines = || {
+   vec![
+    tailed_line(
+     2,
+     "2021-08-19T20:40:56\t100\tPARTY\t15161621\tL,A.M.\tfirst",
+    ),
+    tailed_line(
+     120,
+     "2021-08-19T20:40:56\t101\tPARTY\t15161621\tL,A.M.\tsecond",
+    ),
+   ]
+  };
+  let logs = get_new_chat_logs(
+   Path::new("ChatLog.txt"),
+   lines(),
+   &mut cursor,
+   &mut chat_records,
+  )
+  .await
+  .unwrap();
+  assert_eq!(logs.len(), 2);
+  let logs = get_new_chat_logs(
+   Path::new("ChatLog.txt"),
+   lines(),
+   &mut cursor,
+   &mut chat_records,
+  )
+  .await
+  .unwrap();
+  assert!(logs.is_empty());
+ }
+
+ #[tokio::test]
+ async fn multiline_chat_split_across_polls_is_delivered_once_complete() {
+  let mut cursor = None;
+  let mut chat_records = ChatRecordReader::default();
+  let path = Path::new("ChatLog.txt");
+
+  let first = vec![tailed_line(
+   2,
+   "2021-08-19T20:41:10\t102\tPARTY\t15161621\tL,A.M.\t\"1行目",
+  )];
+  let logs = get_new_chat_logs(path, first, &mut cursor, &mut chat_records)
+   .await
+   .unwrap();
+  assert!(logs.is_empty());
+
+  let rest = vec![tailed_line(80, "2行目"), tailed_line(90, "3行目\"")];
+  let logs = get_new_chat_logs(path, rest, &mut cursor, &mut chat_records)
+   .await
+   .unwrap();
+  assert_eq!(logs.len(), 1);
+  assert_eq!(logs[0].get_body_or_item(), "1行目\n2行目\n3行目");
+ }
+
+ #[tokio::test]
+ async fn malformed_lines_are_skipped_and_counted() {
+  let mut cursor = None;
+  let lines = vec![
+   tailed_line(
+    2,
+    "2021-08-19T20:40:56\t250\t[Pickup]\t15161621\tL,A.M.\t\tStarGem(3)",
+   ),
+   tailed_line(
+    80,
+    "2021-08-19T20:40:57\t251\t[Pickup]\t15161621\tL,A.M.\tN-グラインダー\tNum(1)",
+   ),
+  ];
+  let skipped = SKIPPED_LINE_COUNT.load(Ordering::Relaxed);
+  let logs = get_new_action_logs(Path::new("ActionLog.txt"), lines, &mut cursor)
+   .await
+   .unwrap();
+  assert_eq!(logs.len(), 1);
+  assert!(SKIPPED_LINE_COUNT.load(Ordering::Relaxed) > skipped);
+ }
+
+ fn append_log(path: &Path, text: &str) {
+  let mut file = fs::OpenOptions::new()
+   .create(true)
+   .append(true)
+   .open(path)
+   .unwrap();
+  file.write_all(text.as_bytes()).unwrap();
+ }
+
+ #[tokio::test]
+ async fn rotated_log_files_are_drained_in_both_directories() {
+  let log_root =
+   std::env::temp_dir().join(format!("ngs-log-action-rotation-{}", std::process::id()));
+  let _ = fs::remove_dir_all(&log_root);
+  fs::create_dir_all(log_root.join("log_ngs")).unwrap();
+  fs::create_dir_all(log_root.join("log")).unwrap();
+  let ngs_old = log_root.join("log_ngs").join("ChatLog20210819_00.txt");
+  let ngs_new = log_root.join("log_ngs").join("ChatLog20210820_00.txt");
+  let pso2 = log_root.join("log").join("ChatLog20210819_00.txt");
+  append_log(
+   &ngs_old,
+   "2021-08-19T23:00:00\t1\tPARTY\t15161621\tL,A.M.\told\r\n",
+  );
+  append_log(
+   &pso2,
+   "2021-08-19T23:00:00\t1\tPARTY\t15161621\tL,A.M.\told\r\n",
+  );
+  let mut log_tailers = LogTailers::initialize(log_root.clone()).await.unwrap();
+
+  // 前回のポーリングの後に以前のファイルへ書き込まれてから新しいファイルに切り替わった
+  append_log(
+   &ngs_old,
+   "2021-08-19T23:59:59\t2\tPARTY\t15161621\tL,A.M.\tngs-last\r\n",
+  );
+  append_log(
+   &ngs_new,
+   "2021-08-20T00:00:01\t1\tPARTY\t15161621\tL,A.M.\tngs-next\r\n",
+  );
+  append_log(
+   &pso2,
+   "2021-08-19T23:59:58\t2\tPARTY\t15161621\tL,A.M.\tpso2\r\n",
+  );
+  let logs = get_new_logs(&mut log_tailers).await.unwrap();
+  let bodies: Vec<_> = logs.iter().map(|l| l.get_body_or_item()).collect();
+  assert_eq!(bodies, vec!["pso2", "ngs-last", "ngs-next"]);
+  assert_eq!(log_tailers.files.len(), 2);
+
+  append_log(
+   &ngs_new,
+   "2021-08-20T00:00:02\t2\tPARTY\t15161621\tL,A.M.\tngs-more\r\n",
+  );
+  let logs = get_new_logs(&mut log_tailers).await.unwrap();
+  assert_eq!(logs.len(), 1);
+  fs::remove_dir_all(&log_root).unwrap();
+ }
 }
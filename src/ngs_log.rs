@@ -1,7 +1,8 @@
 use chrono::{DateTime, FixedOffset};
 use num_format::{Locale, ToFormattedString};
 use serde::Deserialize;
-use strum_macros::EnumString;
+use std::fmt;
+use strum_macros::{Display, EnumString};
 
 #[derive(Debug, EnumString, Deserialize, PartialEq, Eq)]
 pub enum NgsLogChannel {
@@ -27,7 +28,7 @@ pub enum NgsLogChannel {
  Group,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct ChatLog {
  pub datetime: DateTime<FixedOffset>,
  pub log_id: u16,
@@ -37,7 +38,7 @@ pub struct ChatLog {
  pub body: String,
 }
 
-#[derive(Debug, EnumString, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Display, EnumString, Deserialize, PartialEq, Eq)]
 pub enum ItemCategory {
  #[strum(serialize = "PICKUP")]
  #[serde(rename = "PICKUP")]
@@ -45,9 +46,83 @@ pub enum ItemCategory {
  #[strum(serialize = "REWARD")]
  #[serde(rename = "REWARD")]
  Reward,
+ /// = NPC への売却
+ #[strum(serialize = "SELL")]
+ #[serde(rename = "SELL")]
+ Sell,
+ /// = 破棄
+ #[strum(serialize = "DISCARD")]
+ #[serde(rename = "DISCARD")]
+ Discard,
+ /// = 倉庫へ移動
+ #[strum(serialize = "STORAGE_IN")]
+ #[serde(rename = "STORAGE_IN")]
+ StorageIn,
+ /// = 倉庫から移動
+ #[strum(serialize = "STORAGE_OUT")]
+ #[serde(rename = "STORAGE_OUT")]
+ StorageOut,
+ /// = マイショップへ出品
+ #[strum(serialize = "SHOP")]
+ #[serde(rename = "SHOP")]
+ DisplayToShop,
+}
+
+impl ItemCategory {
+ /// アイテムやメセタを入手したログか
+ pub fn is_acquisition(&self) -> bool {
+  matches!(self, ItemCategory::Pickup | ItemCategory::Reward)
+ }
+}
+
+/// リワードの受け取り先
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+pub enum ItemDestination {
+ /// = 所持メセタ
+ #[serde(rename = "MESETA")]
+ Meseta,
+ /// = 所持品 ( Backpack )
+ #[serde(rename = "INVENTORY")]
+ Inventory,
+ /// = 倉庫 ( Warehouse )
+ #[serde(rename = "STORAGE")]
+ Storage,
+ /// 上記以外の受け取り先（ログの値をそのまま保持します）
+ #[serde(skip_deserializing)]
+ Other(String),
+}
+
+impl ItemDestination {
+ /// リワードログの受け取り先の列から判定します
+ pub fn from_column(value: &str) -> Self {
+  match value {
+   "Meseta" => ItemDestination::Meseta,
+   "Backpack" | "Inventory" => ItemDestination::Inventory,
+   "Warehouse" | "Storage" => ItemDestination::Storage,
+   other => ItemDestination::Other(other.to_string()),
+  }
+ }
 }
 
-#[derive(Debug)]
+impl fmt::Display for ItemDestination {
+ fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+  match self {
+   ItemDestination::Meseta => write!(f, "MESETA"),
+   ItemDestination::Inventory => write!(f, "INVENTORY"),
+   ItemDestination::Storage => write!(f, "STORAGE"),
+   ItemDestination::Other(other) => write!(f, "{}", other),
+  }
+ }
+}
+
+/// 武器や防具の属性 例: attr:NONE(0)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ItemAttribute {
+ pub element: String,
+ pub value: u32,
+}
+
+#[derive(Debug, PartialEq)]
 pub struct ItemLog {
  pub datetime: DateTime<FixedOffset>,
  pub log_id: u16,
@@ -56,12 +131,112 @@ pub struct ItemLog {
  pub name: String,
  pub item: String,
  pub count: u32,
+ /// 例: Level(13)
+ pub level: Option<u32>,
+ /// 例: attr:NONE(0)
+ pub attribute: Option<ItemAttribute>,
+ /// メセタ入手後の所持メセタ 例: CurrentMeseta(26029094)
+ pub current_meseta: Option<u64>,
+ /// 売却額や出品額 例: Meseta(120) （メセタを入手したログでは count になります）
+ pub meseta: Option<u32>,
+ /// リワードの受け取り先（リワード以外は None）
+ pub destination: Option<ItemDestination>,
+}
+
+impl ItemLog {
+ /// get / post アクションに渡すアイテムの詳細 ( 名前, 値 ) 。値の無い項目は含みません
+ pub fn get_properties(&self) -> Vec<(&'static str, String)> {
+  let mut properties = vec![
+   ("item", self.item.clone()),
+   ("count", self.count.to_string()),
+  ];
+  if let Some(level) = self.level {
+   properties.push(("level", level.to_string()));
+  }
+  if let Some(ref attribute) = self.attribute {
+   properties.push(("attribute", attribute.element.clone()));
+   properties.push(("attribute_value", attribute.value.to_string()));
+  }
+  if let Some(current_meseta) = self.current_meseta {
+   properties.push(("current_meseta", current_meseta.to_string()));
+  }
+  if let Some(meseta) = self.meseta {
+   properties.push(("meseta", meseta.to_string()));
+  }
+  if let Some(ref destination) = self.destination {
+   properties.push(("destination", destination.to_string()));
+  }
+  properties
+ }
+
+ /// 売却で入手したメセタ（売却以外のログは None ）
+ pub fn get_sale_income(&self) -> Option<u32> {
+  match self.category {
+   ItemCategory::Sell => self.meseta,
+   _ => None,
+  }
+ }
+
+ /// 取得アイテム集計のキー。入手以外のカテゴリーは入手と区別して集計します
+ pub fn get_counter_key(&self) -> String {
+  match self.category.is_acquisition() {
+   true => self.item.clone(),
+   false => format!("{} [{}]", self.item, self.category),
+  }
+ }
+}
+
+/// シンボルアートのチャット（アート自体はログに含まれません）
+#[derive(Debug, PartialEq)]
+pub struct SymbolChatLog {
+ pub datetime: DateTime<FixedOffset>,
+ pub log_id: u16,
+ pub channel: NgsLogChannel,
+ pub player_id: u32,
+ pub name: String,
+ /// シンボルアートの識別子
+ pub symbol_art_id: String,
+}
+
+/// スクラッチ（ AC スクラッチ, SG スクラッチ, FUN スクラッチ等）の結果
+#[derive(Debug, PartialEq)]
+pub struct ScratchLog {
+ pub datetime: DateTime<FixedOffset>,
+ pub log_id: u16,
+ /// スクラッチの種類 例: ACScratch
+ pub scratch: String,
+ pub player_id: u32,
+ pub name: String,
+ pub item: String,
+ pub count: u32,
 }
 
-#[derive(Debug)]
+/// スタージェムの取引
+#[derive(Debug, PartialEq)]
+pub struct StarGemLog {
+ pub datetime: DateTime<FixedOffset>,
+ pub log_id: u16,
+ /// 取引の種類 例: Purchase
+ pub category: String,
+ pub player_id: u32,
+ pub name: String,
+ /// 購入したアイテムや取引の内容
+ pub detail: String,
+ /// 増減したスタージェム（消費した場合は負の値）
+ pub star_gem: i64,
+ /// 取引後の所持スタージェム
+ pub current_star_gem: Option<u64>,
+}
+
+/// バリアント名はログファイルの種類 ( ChatLog*.txt など ) に合わせています
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, PartialEq)]
 pub enum NgsLog {
  ChatLog(ChatLog),
  ItemLog(ItemLog),
+ SymbolChatLog(SymbolChatLog),
+ ScratchLog(ScratchLog),
+ StarGemLog(StarGemLog),
 }
 
 impl NgsLog {
@@ -69,24 +244,73 @@ impl NgsLog {
   match self {
    NgsLog::ChatLog(log) => &log.datetime,
    NgsLog::ItemLog(log) => &log.datetime,
+   NgsLog::SymbolChatLog(log) => &log.datetime,
+   NgsLog::ScratchLog(log) => &log.datetime,
+   NgsLog::StarGemLog(log) => &log.datetime,
+  }
+ }
+ pub fn get_datetime_mut(&mut self) -> &mut DateTime<FixedOffset> {
+  match self {
+   NgsLog::ChatLog(log) => &mut log.datetime,
+   NgsLog::ItemLog(log) => &mut log.datetime,
+   NgsLog::SymbolChatLog(log) => &mut log.datetime,
+   NgsLog::ScratchLog(log) => &mut log.datetime,
+   NgsLog::StarGemLog(log) => &mut log.datetime,
+  }
+ }
+ pub fn get_log_id(&self) -> u16 {
+  match self {
+   NgsLog::ChatLog(log) => log.log_id,
+   NgsLog::ItemLog(log) => log.log_id,
+   NgsLog::SymbolChatLog(log) => log.log_id,
+   NgsLog::ScratchLog(log) => log.log_id,
+   NgsLog::StarGemLog(log) => log.log_id,
+  }
+ }
+ pub fn get_player_id(&self) -> u32 {
+  match self {
+   NgsLog::ChatLog(log) => log.player_id,
+   NgsLog::ItemLog(log) => log.player_id,
+   NgsLog::SymbolChatLog(log) => log.player_id,
+   NgsLog::ScratchLog(log) => log.player_id,
+   NgsLog::StarGemLog(log) => log.player_id,
   }
  }
  pub fn get_channel(&self) -> Option<&NgsLogChannel> {
   match self {
    NgsLog::ChatLog(log) => Some(&log.channel),
-   NgsLog::ItemLog(_) => None,
+   NgsLog::SymbolChatLog(log) => Some(&log.channel),
+   _ => None,
+  }
+ }
+ pub fn get_item_log(&self) -> Option<&ItemLog> {
+  match self {
+   NgsLog::ItemLog(log) => Some(log),
+   _ => None,
+  }
+ }
+ pub fn get_category(&self) -> Option<&ItemCategory> {
+  match self {
+   NgsLog::ItemLog(log) => Some(&log.category),
+   _ => None,
   }
  }
  pub fn get_channel_or_category_string(&self) -> String {
   match self {
    NgsLog::ChatLog(log) => format!("{:?}", log.channel),
-   NgsLog::ItemLog(log) => format!("{:?}", log.category),
+   NgsLog::ItemLog(log) => log.category.to_string(),
+   NgsLog::SymbolChatLog(log) => format!("{:?}", log.channel),
+   NgsLog::ScratchLog(log) => log.scratch.clone(),
+   NgsLog::StarGemLog(log) => log.category.clone(),
   }
  }
  pub fn get_name(&self) -> &String {
   match self {
    NgsLog::ChatLog(log) => &log.name,
    NgsLog::ItemLog(log) => &log.name,
+   NgsLog::SymbolChatLog(log) => &log.name,
+   NgsLog::ScratchLog(log) => &log.name,
+   NgsLog::StarGemLog(log) => &log.name,
   }
  }
  // pub fn get_body(&self) -> Option<&String> {
@@ -95,19 +319,13 @@ impl NgsLog {
  //   NgsLog::ItemLog(_) => None,
  //  }
  // }
- pub fn append_body(&mut self, s: &str) -> Option<()> {
-  match self {
-   NgsLog::ChatLog(log) => {
-    log.body = format!("{}\n{}", log.body, s);
-    Some(())
-   }
-   NgsLog::ItemLog(_) => None,
-  }
- }
  pub fn get_body_or_item(&self) -> &String {
   match self {
    NgsLog::ChatLog(log) => &log.body,
    NgsLog::ItemLog(log) => &log.item,
+   NgsLog::SymbolChatLog(log) => &log.symbol_art_id,
+   NgsLog::ScratchLog(log) => &log.item,
+   NgsLog::StarGemLog(log) => &log.detail,
   }
  }
  pub fn get_body_or_item_with_count(&self) -> String {
@@ -118,6 +336,38 @@ impl NgsLog {
     log.item,
     log.count.to_formatted_string(&Locale::ja)
    ),
+   NgsLog::SymbolChatLog(log) => format!("[シンボルアート] {}", log.symbol_art_id),
+   NgsLog::ScratchLog(log) => format!(
+    "{} × {}",
+    log.item,
+    log.count.to_formatted_string(&Locale::ja)
+   ),
+   NgsLog::StarGemLog(log) => match log.current_star_gem {
+    Some(current_star_gem) => format!(
+     "{} ( {:+} SG 残り {} SG )",
+     log.detail,
+     log.star_gem,
+     current_star_gem.to_formatted_string(&Locale::ja)
+    ),
+    None => format!("{} ( {:+} SG )", log.detail, log.star_gem),
+   },
   }
  }
 }
+
+/// テスト用の 2021-08-19T20:40:56+09:00 のチャットログ
+#[cfg(test)]
+pub fn chat_log(channel: NgsLogChannel, name: &str, body: &str) -> NgsLog {
+ use chrono::TimeZone;
+ NgsLog::ChatLog(ChatLog {
+  datetime: FixedOffset::east_opt(9 * 3600)
+   .unwrap()
+   .with_ymd_and_hms(2021, 8, 19, 20, 40, 56)
+   .unwrap(),
+  log_id: 0,
+  channel,
+  player_id: 0,
+  name: name.to_string(),
+  body: body.to_string(),
+ })
+}
@@ -0,0 +1,822 @@
+use crate::ngs_log::{
+ ChatLog, ItemAttribute, ItemCategory, ItemDestination, ItemLog, NgsLog, NgsLogChannel, ScratchLog,
+ StarGemLog, SymbolChatLog,
+};
+use crate::timezone;
+use chrono::{DateTime, FixedOffset, NaiveDateTime};
+use std::str::FromStr;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ParseError {
+ #[error("invalid datetime: {0}")]
+ InvalidDatetime(String),
+ #[error("missing column: {column}")]
+ MissingColumn { column: &'static str },
+ #[error("invalid {column}: {value}")]
+ InvalidColumn { column: &'static str, value: String },
+ #[error("unterminated quoted column")]
+ UnterminatedQuote,
+}
+
+impl ParseError {
+ /// 解析に失敗した列の名前
+ pub fn column(&self) -> Option<&'static str> {
+  match self {
+   ParseError::MissingColumn { column } | ParseError::InvalidColumn { column, .. } => Some(column),
+   ParseError::InvalidDatetime(_) => Some("datetime"),
+   ParseError::UnterminatedQuote => Some("body"),
+  }
+ }
+}
+
+/// タブ区切りの列を列名付きで取り出すためのイテレーター
+struct Columns<'a, I: Iterator<Item = &'a str>> {
+ columns: I,
+}
+
+impl<'a> Columns<'a, std::str::Split<'a, char>> {
+ fn new(s: &'a str) -> Self {
+  Self {
+   columns: s.split('\t'),
+  }
+ }
+}
+
+impl<'a, I: Iterator<Item = &'a str>> Columns<'a, I> {
+ fn from_fields(columns: I) -> Self {
+  Self { columns }
+ }
+
+ fn next(&mut self, column: &'static str) -> Result<&'a str, ParseError> {
+  self
+   .columns
+   .next()
+   .ok_or(ParseError::MissingColumn { column })
+ }
+
+ fn next_optional(&mut self) -> Option<&'a str> {
+  self.columns.next()
+ }
+
+ fn parse<T: FromStr>(&mut self, column: &'static str) -> Result<T, ParseError> {
+  let value = self.next(column)?;
+  value.parse().map_err(|_| ParseError::InvalidColumn {
+   column,
+   value: value.to_string(),
+  })
+ }
+
+ /// 残りの列をタブで連結して取り出します
+ fn rest(self, column: &'static str) -> Result<String, ParseError> {
+  let rest: Vec<_> = self.columns.collect();
+  match rest.is_empty() {
+   true => Err(ParseError::MissingColumn { column }),
+   false => Ok(rest.join("\t")),
+  }
+ }
+}
+
+/// ログの日時はオフセットの無い現地時刻なので、ログのタイムゾーンでのその日時のオフセットを付けます
+fn parse_datetime(datetime_string: &str) -> Result<DateTime<FixedOffset>, ParseError> {
+ let naive = NaiveDateTime::parse_from_str(datetime_string, "%Y-%m-%dT%H:%M:%S")
+  .map_err(|_| ParseError::InvalidDatetime(datetime_string.to_string()))?;
+ Ok(timezone::log_timezone().localize(naive, None))
+}
+
+/// 行頭の日時の列とそれ以降の列に分けます
+fn split_datetime(line: &str) -> Result<(DateTime<FixedOffset>, &str), ParseError> {
+ let (first_column, tail) = line
+  .split_once('\t')
+  .ok_or(ParseError::MissingColumn { column: "log_id" })?;
+ let datetime = parse_datetime(first_column)?;
+ Ok((datetime, tail))
+}
+
+/// `Level(13)` や `attr:NONE(0)` のような `名前(値)` 形式の列を名前と値に分けます
+fn split_parenthesized(value: &str) -> Option<(&str, &str)> {
+ let begin = value.find('(')?;
+ let value_part = value[begin + 1..].strip_suffix(')')?;
+ Some((&value[..begin], value_part))
+}
+
+/// `Num(1)` や `Meseta(12)` のような `名前(値)` 形式の列から値を取り出します
+fn parse_parenthesized(column: &'static str, value: &str) -> Result<u32, ParseError> {
+ let invalid = || ParseError::InvalidColumn {
+  column,
+  value: value.to_string(),
+ };
+ let begin = value.find('(').ok_or_else(invalid)?;
+ let end = value.rfind(')').ok_or_else(invalid)?;
+ if end < begin {
+  return Err(invalid());
+ }
+ value[begin + 1..end].parse().map_err(|_| invalid())
+}
+
+/// 閉じられていない " で後続のログを飲み込み続けないための1レコードの最大行数
+const MAX_CHAT_RECORD_LINES: u64 = 100;
+
+/// チャットログの1レコード（複数行のチャットは複数行にまたがります）
+#[derive(Debug)]
+pub struct ChatRecord {
+ /// レコードの1行目の行頭のバイト位置
+ pub position: u64,
+ /// レコードの1行目の行番号
+ pub line_number: u64,
+ /// レコードの元の行（複数行の場合は改行で連結）
+ pub raw: String,
+ pub fields: Vec<String>,
+ /// " が閉じられないまま最大行数に達したレコード
+ pub is_unterminated: bool,
+}
+
+/// チャットログの TSV を " の囲みを考慮してレコード単位に組み立てるリーダー
+///
+/// " で囲まれた列は改行やタブ、 "" (= ") を含められます。
+/// レコードが完結するまで読みかけのレコードを保持するので、ポーリングをまたいで書き込まれた
+/// 複数行のチャットも1つのレコードになります。
+#[derive(Default)]
+pub struct ChatRecordReader {
+ partial: Option<PartialChatRecord>,
+}
+
+struct PartialChatRecord {
+ record: ChatRecord,
+ line_count: u64,
+ current_field: String,
+}
+
+impl ChatRecordReader {
+ /// 1行を読み込み、レコードが完結していればそのレコードを返します
+ pub fn push_line(&mut self, position: u64, line_number: u64, line: &str) -> Option<ChatRecord> {
+  // レコードの間の空行は読み飛ばします
+  if self.partial.is_none() && line.is_empty() {
+   return None;
+  }
+  // 前の行から " の囲みが続いている場合は改行も列の一部です
+  let (mut partial, mut is_quoted) = match self.partial.take() {
+   Some(mut partial) => {
+    partial.record.raw.push('\n');
+    partial.current_field.push('\n');
+    partial.line_count += 1;
+    (partial, true)
+   }
+   None => (
+    PartialChatRecord {
+     record: ChatRecord {
+      position,
+      line_number,
+      raw: String::new(),
+      fields: Vec::new(),
+      is_unterminated: false,
+     },
+     line_count: 1,
+     current_field: String::new(),
+    },
+    false,
+   ),
+  };
+  partial.record.raw.push_str(line);
+
+  let mut is_field_start = !is_quoted;
+  let mut chars = line.chars().peekable();
+  while let Some(c) = chars.next() {
+   match c {
+    '"' if is_quoted && chars.peek() == Some(&'"') => {
+     chars.next();
+     partial.current_field.push('"');
+    }
+    '"' if is_quoted => is_quoted = false,
+    '"' if is_field_start => is_quoted = true,
+    '\t' if !is_quoted => {
+     let field = std::mem::take(&mut partial.current_field);
+     partial.record.fields.push(field);
+     is_field_start = true;
+     continue;
+    }
+    c => partial.current_field.push(c),
+   }
+   is_field_start = false;
+  }
+
+  if is_quoted && partial.line_count < MAX_CHAT_RECORD_LINES {
+   self.partial = Some(partial);
+   return None;
+  }
+  let mut record = partial.record;
+  record.fields.push(partial.current_field);
+  record.is_unterminated = is_quoted;
+  Some(record)
+ }
+
+ /// 読みかけのレコードを破棄します
+ pub fn clear(&mut self) {
+  self.partial = None;
+ }
+}
+
+/// チャットログの1行を record_reader に読み込み、レコードが完結していればそのレコードと解析結果を返します
+///
+/// 複数行のチャットは最後の行を読み込んだときに1つのログになります。
+pub fn parse_chat_line(
+ record_reader: &mut ChatRecordReader,
+ position: u64,
+ line_number: u64,
+ line: &str,
+) -> Option<(ChatRecord, Result<NgsLog, ParseError>)> {
+ let record = record_reader.push_line(position, line_number, line)?;
+ let ngs_log = parse_chat_record(&record);
+ Some((record, ngs_log))
+}
+
+/// チャットログの1レコードを解析します
+fn parse_chat_record(record: &ChatRecord) -> Result<NgsLog, ParseError> {
+ if record.is_unterminated {
+  return Err(ParseError::UnterminatedQuote);
+ }
+ let mut columns = Columns::from_fields(record.fields.iter().map(String::as_str));
+ let datetime = parse_datetime(columns.next("datetime")?)?;
+ let log_id = columns.parse("log_id")?;
+ let channel_string = columns.next("channel")?;
+ let channel = NgsLogChannel::from_str(channel_string).map_err(|_| ParseError::InvalidColumn {
+  column: "channel",
+  value: channel_string.to_string(),
+ })?;
+ let player_id = columns.parse("player_id")?;
+ let name = columns.next("name")?.to_string();
+ // 本文に " で囲まれていないタブが含まれていても本文の一部として扱います
+ let body = columns.rest("body")?;
+ Ok(NgsLog::ChatLog(ChatLog {
+  datetime,
+  log_id,
+  channel,
+  player_id,
+  name,
+  body,
+ }))
+}
+
+/// アイテムの数量や Lv などの `名前(値)` 形式の列
+#[derive(Default)]
+struct ItemColumns<'a> {
+ /// 最初の列（エラー表示用）
+ first_column: Option<&'a str>,
+ num: Option<u32>,
+ meseta: Option<u32>,
+ level: Option<u32>,
+ attribute: Option<ItemAttribute>,
+ current_meseta: Option<u64>,
+}
+
+impl<'a> ItemColumns<'a> {
+ /// 残りの列を解析します。 `名前(値)` 形式でない列は無視します
+ fn parse<I: Iterator<Item = &'a str>>(columns: &mut Columns<'a, I>) -> Result<Self, ParseError> {
+  let mut item_columns = Self::default();
+  while let Some(column) = columns.next_optional() {
+   item_columns.first_column.get_or_insert(column);
+   let (key, value) = match split_parenthesized(column) {
+    Some(key_value) => key_value,
+    None => continue,
+   };
+   let invalid = |name| ParseError::InvalidColumn {
+    column: name,
+    value: column.to_string(),
+   };
+   match key {
+    "Num" => item_columns.num = Some(value.parse().map_err(|_| invalid("count"))?),
+    "Meseta" => item_columns.meseta = Some(value.parse().map_err(|_| invalid("count"))?),
+    "CurrentMeseta" => {
+     item_columns.current_meseta = Some(value.parse().map_err(|_| invalid("current_meseta"))?)
+    }
+    "Level" => item_columns.level = Some(value.parse().map_err(|_| invalid("level"))?),
+    _ => {
+     if let Some(element) = key.strip_prefix("attr:") {
+      item_columns.attribute = Some(ItemAttribute {
+       element: element.to_string(),
+       value: value.parse().map_err(|_| invalid("attribute"))?,
+      });
+     }
+    }
+   }
+  }
+  Ok(item_columns)
+ }
+
+ /// メセタのログの入手額
+ fn meseta(&self) -> Result<u32, ParseError> {
+  match (self.meseta, self.first_column) {
+   (Some(meseta), _) => Ok(meseta),
+   (None, Some(column)) => Err(ParseError::InvalidColumn {
+    column: "count",
+    value: column.to_string(),
+   }),
+   (None, None) => Err(ParseError::MissingColumn { column: "count" }),
+  }
+ }
+}
+
+/// アクションログの1行を解析します。扱わないカテゴリーの行は None になります
+pub fn parse_action_line(line: &str) -> Result<Option<NgsLog>, ParseError> {
+ let (datetime, tail) = split_datetime(line)?;
+ let mut columns = Columns::new(tail);
+ let log_id = columns.parse("log_id")?;
+ let category = match columns.next("category")? {
+  "[Pickup]" => ItemCategory::Pickup,
+  "[Sell]" => ItemCategory::Sell,
+  "[Discard]" => ItemCategory::Discard,
+  "[Warehouse]" => ItemCategory::StorageIn,
+  "[Backpack]" => ItemCategory::StorageOut,
+  "[DisplayToShop]" => ItemCategory::DisplayToShop,
+  _ => return Ok(None),
+ };
+ let player_id = columns.parse("player_id")?;
+ let name = columns.next("name")?.to_string();
+ let item = columns.next("item")?;
+
+ // 例: 2021-08-19T20:40:17	243	[Pickup]	15161621	L,A.M.	N-グラインダー	Num(1)
+ //     2021-08-19T20:40:56	249	[Pickup]	15161621	L,A.M.	ツヴィアアーマ
+ //     2021-08-19T20:40:56	250	[Pickup]	15161621	L,A.M.		Meseta(12)	CurrentMeseta(26029094)
+ //     2021-08-19T20:55:51	406	[Pickup]	15161621	L,A.M.	ツヴィアダガー	attr:NONE(0)
+ //     2021-09-03T10:20:08	477	[Pickup]	15161621	L,A.M.	サプライズナックル	Level(13)
+ let item_columns = ItemColumns::parse(&mut columns)?;
+
+ //     2021-09-03T10:22:00	479	[Sell]	15161621	L,A.M.	ツヴィアダガー	Num(1)	Meseta(120)
+ // Level(13) や attr:NONE(0) は数量ではありません
+ let (item, count, meseta) = match item.is_empty() {
+  true => ("Meseta".to_string(), item_columns.meseta()?, None),
+  false => (
+   item.to_string(),
+   item_columns.num.unwrap_or(1),
+   item_columns.meseta,
+  ),
+ };
+ Ok(Some(NgsLog::ItemLog(ItemLog {
+  datetime,
+  log_id,
+  category,
+  player_id,
+  name,
+  item,
+  count,
+  level: item_columns.level,
+  attribute: item_columns.attribute,
+  current_meseta: item_columns.current_meseta,
+  meseta,
+  destination: None,
+ })))
+}
+
+/// `[ACScratch]` のような角括弧で囲まれた列から中身を取り出します
+fn strip_brackets(value: &str) -> &str {
+ value
+  .strip_prefix('[')
+  .and_then(|v| v.strip_suffix(']'))
+  .unwrap_or(value)
+}
+
+/// シンボルアートチャットログの1行を解析します
+pub fn parse_symbol_chat_line(line: &str) -> Result<NgsLog, ParseError> {
+ // 例: 2021-08-19T20:41:30	103	PARTY	15161621	L,A.M.	c1a2b3d4-0000-4000-8000-0123456789ab
+ let (datetime, tail) = split_datetime(line)?;
+ let mut columns = Columns::new(tail);
+ let log_id = columns.parse("log_id")?;
+ let channel_string = columns.next("channel")?;
+ let channel = NgsLogChannel::from_str(channel_string).map_err(|_| ParseError::InvalidColumn {
+  column: "channel",
+  value: channel_string.to_string(),
+ })?;
+ let player_id = columns.parse("player_id")?;
+ let name = columns.next("name")?.to_string();
+ let symbol_art_id = columns.next_optional().unwrap_or_default().to_string();
+ Ok(NgsLog::SymbolChatLog(SymbolChatLog {
+  datetime,
+  log_id,
+  channel,
+  player_id,
+  name,
+  symbol_art_id,
+ }))
+}
+
+/// スクラッチログの1行を解析します
+pub fn parse_scratch_line(line: &str) -> Result<NgsLog, ParseError> {
+ // 例: 2021-09-03T21:00:00	12	[ACScratch]	15161621	L,A.M.	ラッピースーツ	Num(1)
+ //     2021-09-03T21:00:05	13	[SGScratch]	15161621	L,A.M.	C/ストラーガⅢ
+ let (datetime, tail) = split_datetime(line)?;
+ let mut columns = Columns::new(tail);
+ let log_id = columns.parse("log_id")?;
+ let scratch = strip_brackets(columns.next("scratch")?).to_string();
+ let player_id = columns.parse("player_id")?;
+ let name = columns.next("name")?.to_string();
+ let item = columns.next("item")?.to_string();
+ let count = match columns.next_optional() {
+  Some(column) => parse_parenthesized("count", column)?,
+  None => 1,
+ };
+ Ok(NgsLog::ScratchLog(ScratchLog {
+  datetime,
+  log_id,
+  scratch,
+  player_id,
+  name,
+  item,
+  count,
+ }))
+}
+
+/// スタージェムログの1行を解析します
+pub fn parse_star_gem_line(line: &str) -> Result<NgsLog, ParseError> {
+ // 例: 2021-09-03T20:59:00	5	[Purchase]	15161621	L,A.M.	ACスクラッチチケット	StarGem(-30)	CurrentStarGem(270)
+ //     2021-09-03T21:10:00	6	[Reward]	15161621	L,A.M.	デイリータスク	StarGem(5)
+ let (datetime, tail) = split_datetime(line)?;
+ let mut columns = Columns::new(tail);
+ let log_id = columns.parse("log_id")?;
+ let category = strip_brackets(columns.next("category")?).to_string();
+ let player_id = columns.parse("player_id")?;
+ let name = columns.next("name")?.to_string();
+ let detail = columns.next("detail")?.to_string();
+ let mut star_gem = None;
+ let mut current_star_gem = None;
+ while let Some(column) = columns.next_optional() {
+  let invalid = |name| ParseError::InvalidColumn {
+   column: name,
+   value: column.to_string(),
+  };
+  match split_parenthesized(column) {
+   Some(("StarGem", value)) => star_gem = Some(value.parse().map_err(|_| invalid("star_gem"))?),
+   Some(("CurrentStarGem", value)) => {
+    current_star_gem = Some(value.parse().map_err(|_| invalid("current_star_gem"))?)
+   }
+   _ => {}
+  }
+ }
+ Ok(NgsLog::StarGemLog(StarGemLog {
+  datetime,
+  log_id,
+  category,
+  player_id,
+  name,
+  detail,
+  star_gem: star_gem.ok_or(ParseError::MissingColumn { column: "star_gem" })?,
+  current_star_gem,
+ }))
+}
+
+/// リワードログの1行を解析します
+pub fn parse_reward_line(line: &str) -> Result<NgsLog, ParseError> {
+ // 例: 2021-08-20T21:03:11	12	15161621	L,A.M.	Meseta	Meseta(1500)
+ //     2021-08-20T21:03:11	13	15161621	L,A.M.	Backpack	C/エアルノート	Num(3)
+ //     2021-08-20T21:03:12	14	15161621	L,A.M.	Warehouse	N-グラインダー	Num(10)
+ let (datetime, tail) = split_datetime(line)?;
+ let mut columns = Columns::new(tail);
+ let category = ItemCategory::Reward;
+ let log_id = columns.parse("log_id")?;
+ let player_id = columns.parse("player_id")?;
+ let name = columns.next("name")?.to_string();
+ let destination = ItemDestination::from_column(columns.next("destination")?);
+ let (item, item_columns) = match destination {
+  ItemDestination::Meseta => ("Meseta".to_string(), ItemColumns::parse(&mut columns)?),
+  _ => {
+   let item = columns.next("item")?.to_string();
+   (item, ItemColumns::parse(&mut columns)?)
+  }
+ };
+ let count = match destination {
+  ItemDestination::Meseta => item_columns.meseta()?,
+  _ => item_columns.num.unwrap_or(1),
+ };
+ Ok(NgsLog::ItemLog(ItemLog {
+  datetime,
+  log_id,
+  category,
+  player_id,
+  name,
+  item,
+  count,
+  level: item_columns.level,
+  attribute: item_columns.attribute,
+  current_meseta: item_columns.current_meseta,
+  meseta: None,
+  destination: Some(destination),
+ }))
+}
+
+#[cfg(test)]
+mod tests {
+ use super::*;
+
+ /// 1行で完結しているチャットログを解析します
+ fn parse_single_chat_line(line: &str) -> Result<NgsLog, ParseError> {
+  match parse_chat_line(&mut ChatRecordReader::default(), 0, 1, line) {
+   Some((_, ngs_log)) => ngs_log,
+   None => Err(ParseError::UnterminatedQuote),
+  }
+ }
+
+ /// チャットログを複数行のチャットを含めて解析します
+ fn parse_chat_logs(text: &str) -> Result<Vec<NgsLog>, ParseError> {
+  let mut ngs_logs = Vec::new();
+  let mut record_reader = ChatRecordReader::default();
+  for (i, line) in text.lines().enumerate() {
+   if let Some((_, ngs_log)) = parse_chat_line(&mut record_reader, 0, i as u64 + 1, line) {
+    ngs_logs.push(ngs_log?);
+   }
+  }
+  Ok(ngs_logs)
+ }
+
+ fn parse_action_logs(text: &str) -> Result<Vec<NgsLog>, ParseError> {
+  let mut ngs_logs = Vec::new();
+  for line in text.lines() {
+   ngs_logs.extend(parse_action_line(line)?);
+  }
+  Ok(ngs_logs)
+ }
+
+ fn parse_reward_logs(text: &str) -> Result<Vec<NgsLog>, ParseError> {
+  text.lines().map(parse_reward_line).collect()
+ }
+
+ const CHAT_LOG: &str = include_str!("../tests/fixtures/ChatLog.txt");
+ const ACTION_LOG: &str = include_str!("../tests/fixtures/ActionLog.txt");
+ const REWARD_LOG: &str = include_str!("../tests/fixtures/RewardLog.txt");
+ const SYMBOL_CHAT_LOG: &str = include_str!("../tests/fixtures/SymbolChatLog.txt");
+ const SCRATCH_LOG: &str = include_str!("../tests/fixtures/ScratchLog.txt");
+ const STAR_GEM_LOG: &str = include_str!("../tests/fixtures/StarGemLog.txt");
+
+ fn chat_log(ngs_log: &NgsLog) -> &ChatLog {
+  match ngs_log {
+   NgsLog::ChatLog(log) => log,
+   _ => panic!("not a chat log: {:?}", ngs_log),
+  }
+ }
+
+ fn item_log(ngs_log: &NgsLog) -> &ItemLog {
+  match ngs_log {
+   NgsLog::ItemLog(log) => log,
+   _ => panic!("not an item log: {:?}", ngs_log),
+  }
+ }
+
+ #[test]
+ fn parses_chat_log_fixture() {
+  let ngs_logs = parse_chat_logs(CHAT_LOG).unwrap();
+  let bodies: Vec<_> = ngs_logs.iter().map(|l| chat_log(l).body.as_str()).collect();
+  assert_eq!(
+   bodies,
+   vec![
+    "☔雷雨 あります",
+    "/la console2",
+    "1行目\n2行目\n3行目",
+    "彼は\"やあ\"と\n言った",
+    "〘緊急警報発令〙ネクス・ヴェラ",
+    "\n空行から始まる",
+    "\"引用\" と\tタブ",
+   ]
+  );
+  let first = chat_log(&ngs_logs[0]);
+  assert_eq!(first.log_id, 100);
+  assert_eq!(first.channel, NgsLogChannel::Public);
+  assert_eq!(first.player_id, 10000001);
+  assert_eq!(first.name, "ネクス");
+  assert_eq!(chat_log(&ngs_logs[3]).channel, NgsLogChannel::Group);
+ }
+
+ /// PSO2NGS と同じように本文を " で囲み、 " を "" にエスケープします
+ fn quote_body(body: &str) -> String {
+  format!("\"{}\"", body.replace('"', "\"\""))
+ }
+
+ #[test]
+ fn quoted_chat_bodies_round_trip() {
+  let bodies = [
+   "タブ\tを含む",
+   "\"引用\"",
+   "末尾が\"",
+   "\"\"",
+   "1行目\n\"2行目\"\n\n4行目",
+   "\n",
+  ];
+  for body in bodies.iter() {
+   let record = format!(
+    "2021-08-19T20:41:30\t106\tPARTY\t15161621\tL,A.M.\t{}",
+    quote_body(body)
+   );
+   let mut reader = ChatRecordReader::default();
+   let mut records: Vec<_> = record
+    .split('\n')
+    .enumerate()
+    .filter_map(|(i, line)| reader.push_line(0, i as u64 + 1, line))
+    .collect();
+   assert_eq!(records.len(), 1, "{:?}", body);
+   let ngs_log = parse_chat_record(&records.remove(0)).unwrap();
+   assert_eq!(&chat_log(&ngs_log).body, body);
+  }
+ }
+
+ #[test]
+ fn unterminated_quote_does_not_swallow_logs_forever() {
+  let mut reader = ChatRecordReader::default();
+  assert!(reader
+   .push_line(
+    0,
+    1,
+    "2021-08-19T20:41:30\t106\tPARTY\t15161621\tL,A.M.\t\"閉じない"
+   )
+   .is_none());
+  let record = (2..=MAX_CHAT_RECORD_LINES)
+   .find_map(|i| reader.push_line(0, i, "続き"))
+   .unwrap();
+  assert!(matches!(
+   parse_chat_record(&record),
+   Err(ParseError::UnterminatedQuote)
+  ));
+  assert!(reader
+   .push_line(
+    0,
+    1,
+    "2021-08-19T20:41:31\t107\tPARTY\t15161621\tL,A.M.\tnext"
+   )
+   .is_some());
+ }
+
+ #[test]
+ fn parses_action_log_fixture() {
+  let ngs_logs = parse_action_logs(ACTION_LOG).unwrap();
+  let items: Vec<_> = ngs_logs
+   .iter()
+   .map(|l| {
+    let log = item_log(l);
+    (log.category.to_string(), log.item.as_str(), log.count)
+   })
+   .collect();
+  let expected = vec![
+   // Num(1)
+   ("PICKUP", "N-グラインダー", 1),
+   ("PICKUP", "ツヴィアアーマ", 1),
+   // Meseta(12)
+   ("PICKUP", "Meseta", 12),
+   // attr:NONE(0) は数量ではない
+   ("PICKUP", "ツヴィアダガー", 1),
+   // Level(13) は数量ではない
+   ("PICKUP", "サプライズナックル", 1),
+   ("STORAGE_IN", "C/エアルノート", 20),
+   ("SELL", "ツヴィアダガー", 1),
+   ("DISCARD", "モノメイト", 3),
+   ("STORAGE_OUT", "C/エアルノート", 5),
+   ("SHOP", "C/ストラーガⅢ", 1),
+   // [Unknown] は扱わない
+  ];
+  let expected: Vec<_> = expected
+   .into_iter()
+   .map(|(c, i, n)| (c.to_string(), i, n))
+   .collect();
+  assert_eq!(items, expected);
+
+  let meseta = item_log(&ngs_logs[2]);
+  assert_eq!(meseta.current_meseta, Some(26029094));
+  // メセタの入手額は count に入ります
+  assert_eq!(meseta.meseta, None);
+  let sell = item_log(&ngs_logs[6]);
+  assert_eq!(sell.meseta, Some(120));
+  assert_eq!(sell.get_sale_income(), Some(120));
+  let shop = item_log(&ngs_logs[9]);
+  assert_eq!(shop.meseta, Some(500000));
+  assert_eq!(shop.get_sale_income(), None);
+  assert_eq!(item_log(&ngs_logs[0]).meseta, None);
+  let dagger = item_log(&ngs_logs[3]);
+  assert_eq!(
+   dagger.attribute,
+   Some(ItemAttribute {
+    element: "NONE".to_string(),
+    value: 0
+   })
+  );
+  assert_eq!(dagger.level, None);
+  assert_eq!(item_log(&ngs_logs[4]).level, Some(13));
+ }
+
+ #[test]
+ fn parses_reward_log_fixture() {
+  let ngs_logs = parse_reward_logs(REWARD_LOG).unwrap();
+  let items: Vec<_> = ngs_logs
+   .iter()
+   .map(|l| (item_log(l).item.as_str(), item_log(l).count))
+   .collect();
+  assert_eq!(
+   items,
+   vec![
+    ("Meseta", 1500),
+    ("C/エアルノート", 3),
+    ("N-グラインダー", 10),
+    ("サプライズナックル", 1)
+   ]
+  );
+  let destinations: Vec<_> = ngs_logs
+   .iter()
+   .map(|l| item_log(l).destination.clone().unwrap())
+   .collect();
+  assert_eq!(
+   destinations,
+   vec![
+    ItemDestination::Meseta,
+    ItemDestination::Inventory,
+    ItemDestination::Storage,
+    ItemDestination::Other("Mailbox".to_string())
+   ]
+  );
+  assert!(ngs_logs.iter().all(|l| l.get_player_id() == 15161621));
+  assert_eq!(item_log(&ngs_logs[3]).level, Some(13));
+ }
+
+ #[test]
+ fn parses_symbol_chat_scratch_and_star_gem_log_fixtures() {
+  let symbol_chats: Vec<_> = SYMBOL_CHAT_LOG
+   .lines()
+   .map(|line| match parse_symbol_chat_line(line).unwrap() {
+    NgsLog::SymbolChatLog(log) => (log.channel, log.name, log.symbol_art_id),
+    ngs_log => panic!("not a symbol chat log: {:?}", ngs_log),
+   })
+   .collect();
+  assert_eq!(
+   symbol_chats,
+   vec![
+    (
+     NgsLogChannel::Party,
+     "L,A.M.".to_string(),
+     "c1a2b3d4-0000-4000-8000-0123456789ab".to_string()
+    ),
+    (NgsLogChannel::Guild, "ネクス".to_string(), "".to_string()),
+   ]
+  );
+
+  let scratches: Vec<_> = SCRATCH_LOG
+   .lines()
+   .map(|line| match parse_scratch_line(line).unwrap() {
+    NgsLog::ScratchLog(log) => (log.scratch, log.item, log.count),
+    ngs_log => panic!("not a scratch log: {:?}", ngs_log),
+   })
+   .collect();
+  assert_eq!(
+   scratches,
+   vec![
+    ("ACScratch".to_string(), "ラッピースーツ".to_string(), 1),
+    ("SGScratch".to_string(), "C/ストラーガⅢ".to_string(), 1),
+   ]
+  );
+
+  let star_gems: Vec<_> = STAR_GEM_LOG
+   .lines()
+   .map(|line| match parse_star_gem_line(line).unwrap() {
+    NgsLog::StarGemLog(log) => (log.category, log.star_gem, log.current_star_gem),
+    ngs_log => panic!("not a star gem log: {:?}", ngs_log),
+   })
+   .collect();
+  assert_eq!(
+   star_gems,
+   vec![
+    ("Purchase".to_string(), -30, Some(270)),
+    ("Reward".to_string(), 5, None),
+   ]
+  );
+ }
+
+ #[test]
+ fn rejects_malformed_lines() {
+  assert!(matches!(
+   parse_action_line("2021-08-19T20:40:56\t250\t[Pickup]\t15161621\tL,A.M.\t\tStarGem(3)"),
+   Err(ParseError::InvalidColumn {
+    column: "count",
+    ..
+   })
+  ));
+  assert!(matches!(
+   parse_single_chat_line("2021-08-19T20:40:56\t100\tPUBLIC\tnot-a-number\tネクス\tbody"),
+   Err(ParseError::InvalidColumn {
+    column: "player_id",
+    ..
+   })
+  ));
+  assert!(matches!(
+   parse_single_chat_line("2021-08-19T20:40:56\t100\tUNKNOWN\t10000001\tネクス\tbody"),
+   Err(ParseError::InvalidColumn {
+    column: "channel",
+    ..
+   })
+  ));
+  assert!(matches!(
+   parse_reward_line("2021-08-20T21:03:11\t12\t-\tL,A.M.\tMeseta\tMeseta(1500)"),
+   Err(ParseError::InvalidColumn {
+    column: "player_id",
+    ..
+   })
+  ));
+  assert!(matches!(
+   parse_single_chat_line("2021-08-19T20:40:56\t100\tPUBLIC"),
+   Err(ParseError::MissingColumn {
+    column: "player_id"
+   })
+  ));
+ }
+}
@@ -0,0 +1,137 @@
+use chrono::{DateTime, Duration, FixedOffset};
+use serde::Deserialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// cooldown と max_per_minute を数える単位
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+pub enum LimitScope {
+ /// [[if]] ごと
+ #[default]
+ Global,
+ /// 発言者の名前ごと
+ Name,
+ /// 一致したキーワードごと
+ Keyword,
+}
+
+/// [[if]] の cooldown と max_per_minute による発動の制限
+///
+/// 経過時間はログの日時で数えるため、リプレイでも同じように制限されます。
+#[derive(Debug)]
+pub struct RateLimiter {
+ cooldown: Option<Duration>,
+ max_per_minute: Option<u32>,
+ /// 制限の単位ごとの直近1分間（と最後）の発動日時
+ fired: Mutex<HashMap<String, VecDeque<DateTime<FixedOffset>>>>,
+ suppressed: AtomicU64,
+}
+
+impl RateLimiter {
+ pub fn new(cooldown: Option<Duration>, max_per_minute: Option<u32>) -> Self {
+  RateLimiter {
+   cooldown,
+   max_per_minute,
+   fired: Mutex::new(HashMap::new()),
+   suppressed: AtomicU64::new(0),
+  }
+ }
+
+ /// 発動できる場合は発動を記録して None を、制限された場合は制限した設定項目名を返します
+ pub fn acquire(&self, key: &str, datetime: &DateTime<FixedOffset>) -> Option<&'static str> {
+  let mut fired = self.fired.lock().unwrap();
+  let history = fired.entry(key.to_string()).or_default();
+  let limit = match (self.cooldown, self.max_per_minute, history.back()) {
+   (Some(cooldown), _, Some(last)) if *datetime - *last < cooldown => Some("cooldown"),
+   (_, Some(max_per_minute), _)
+    if history
+     .iter()
+     .filter(|fired| *datetime - **fired < Duration::minutes(1))
+     .count()
+     >= max_per_minute as usize =>
+   {
+    Some("max_per_minute")
+   }
+   _ => None,
+  };
+  match limit {
+   Some(_) => {
+    self.suppressed.fetch_add(1, Ordering::Relaxed);
+   }
+   None => {
+    // 最後の発動日時は cooldown のために残します
+    while history.len() > 1
+     && history
+      .front()
+      .is_some_and(|fired| *datetime - *fired >= Duration::minutes(1))
+    {
+     history.pop_front();
+    }
+    history.push_back(*datetime);
+    // 発動しなくなった名前やキーワードの記録が溜まり続けないよう、
+    // cooldown と max_per_minute のどちらにも影響しなくなった記録を削除します
+    let retention = self.cooldown.map_or(Duration::minutes(1), |cooldown| {
+     cooldown.max(Duration::minutes(1))
+    });
+    fired.retain(|_, history| {
+     history
+      .back()
+      .is_some_and(|last| *datetime - *last < retention)
+    });
+   }
+  }
+  limit
+ }
+
+ /// 制限した回数
+ pub fn suppressed_count(&self) -> u64 {
+  self.suppressed.load(Ordering::Relaxed)
+ }
+}
+
+#[cfg(test)]
+mod tests {
+ use super::*;
+ use chrono::TimeZone;
+
+ fn at(minute: u32, second: u32) -> DateTime<FixedOffset> {
+  FixedOffset::east_opt(9 * 3600)
+   .unwrap()
+   .with_ymd_and_hms(2021, 8, 19, 20, minute, second)
+   .unwrap()
+ }
+
+ #[test]
+ fn cooldown_is_counted_per_key() {
+  let limiter = RateLimiter::new(Some(Duration::seconds(30)), None);
+  assert_eq!(limiter.acquire("A", &at(0, 0)), None);
+  assert_eq!(limiter.acquire("A", &at(0, 29)), Some("cooldown"));
+  assert_eq!(limiter.acquire("B", &at(0, 29)), None);
+  assert_eq!(limiter.acquire("A", &at(0, 30)), None);
+  assert_eq!(limiter.suppressed_count(), 1);
+ }
+
+ #[test]
+ fn max_per_minute_uses_a_sliding_window() {
+  let limiter = RateLimiter::new(None, Some(2));
+  assert_eq!(limiter.acquire("", &at(0, 0)), None);
+  assert_eq!(limiter.acquire("", &at(0, 10)), None);
+  assert_eq!(limiter.acquire("", &at(0, 50)), Some("max_per_minute"));
+  assert_eq!(limiter.acquire("", &at(1, 0)), None);
+  assert_eq!(limiter.acquire("", &at(1, 5)), Some("max_per_minute"));
+  assert_eq!(limiter.acquire("", &at(1, 10)), None);
+  assert_eq!(limiter.suppressed_count(), 2);
+ }
+
+ #[test]
+ fn expired_keys_are_pruned_when_firing() {
+  let limiter = RateLimiter::new(Some(Duration::minutes(2)), None);
+  assert_eq!(limiter.acquire("A", &at(0, 0)), None);
+  assert_eq!(limiter.acquire("B", &at(1, 0)), None);
+  assert_eq!(limiter.fired.lock().unwrap().len(), 2);
+  assert_eq!(limiter.acquire("C", &at(2, 0)), None);
+  assert_eq!(limiter.fired.lock().unwrap().len(), 2);
+  assert!(!limiter.fired.lock().unwrap().contains_key("A"));
+ }
+}
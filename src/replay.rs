@@ -0,0 +1,186 @@
+use crate::cli::ReplayArgs;
+use crate::ngs_log::NgsLog;
+use crate::tailer::LogTailer;
+use crate::{action, apply_ngs_log_actions, timezone, ActiveLogFile, LogKind, CONF};
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, FixedOffset, NaiveDateTime};
+use std::{
+ fs,
+ path::{Path, PathBuf},
+};
+
+/// 過去のログを時系列順に [[if]] の設定へ流します
+pub async fn run(args: &ReplayArgs, log_root: Option<PathBuf>) -> Result<()> {
+ let from = args.from.as_deref().map(parse_datetime_arg).transpose()?;
+ let to = args.to.as_deref().map(parse_datetime_arg).transpose()?;
+ if let Some(speed) = args.speed {
+  if speed.is_nan() || speed <= 0.0 {
+   return Err(anyhow!(
+    "--speed には 0 より大きい値を指定してください: {}",
+    speed
+   ));
+  }
+ }
+ if args.no_side_effects {
+  action::enable_dry_run();
+ }
+
+ let paths = match (args.paths.is_empty(), log_root) {
+  (false, _) => args.paths.clone(),
+  (true, Some(log_root)) => vec![log_root],
+  (true, None) => {
+   return Err(anyhow!(
+    "再生するログのフォルダまたはファイルを指定してください"
+   ))
+  }
+ };
+ let mut ngs_logs = Vec::new();
+ for (kind, path) in collect_log_files(&paths)? {
+  ngs_logs.append(&mut read_log_file(kind, path).await?);
+ }
+ ngs_logs.retain(|ngs_log| {
+  let datetime = ngs_log.get_datetime();
+  from.is_none_or(|from| *datetime >= from) && to.is_none_or(|to| *datetime <= to)
+ });
+ ngs_logs.sort_by(|a, b| a.get_datetime().cmp(b.get_datetime()));
+
+ println!(
+  "[System]{}リプレイ開始: {} 件のログ{}",
+  CONF.get_column_separator(),
+  ngs_logs.len(),
+  if action::is_dry_run() {
+   " ( sound, command, get, post は実行しません )"
+  } else {
+   ""
+  }
+ );
+ let replay = async {
+  let mut previous: Option<DateTime<FixedOffset>> = None;
+  for ngs_log in &ngs_logs {
+   if let (Some(speed), Some(previous)) = (args.speed, previous) {
+    let interval = (*ngs_log.get_datetime() - previous)
+     .num_milliseconds()
+     .max(0);
+    tokio::time::sleep(tokio::time::Duration::from_secs_f64(
+     interval as f64 / 1000.0 / speed,
+    ))
+    .await;
+   }
+   previous = Some(*ngs_log.get_datetime());
+   // 集計の経過時間も実時間ではなくログの日時で数えます
+   action::set_replay_time(*ngs_log.get_datetime()).await;
+   apply_ngs_log_actions(ngs_log).await?;
+  }
+  Ok::<(), anyhow::Error>(())
+ };
+ tokio::select! {
+  result = replay => result?,
+  _ = tokio::signal::ctrl_c() => (),
+ }
+ Ok(())
+}
+
+/// `2021-08-19T20:00:00` 形式の日時をログのタイムゾーンで解釈します
+fn parse_datetime_arg(value: &str) -> Result<DateTime<FixedOffset>> {
+ let naive = NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S").map_err(|_| {
+  anyhow!(
+   "日時は 2021-08-19T20:00:00 の形式で指定してください: {}",
+   value
+  )
+ })?;
+ Ok(timezone::log_timezone().localize(naive, None))
+}
+
+/// 指定されたフォルダやファイルから再生するログファイルを集めます
+///
+/// ログフォルダ（ log_ngs と log を含むフォルダ）が指定された場合は両方のフォルダのログファイルを集めます。
+fn collect_log_files(paths: &[PathBuf]) -> Result<Vec<(LogKind, PathBuf)>> {
+ let mut files = Vec::new();
+ for path in paths {
+  if path.is_file() {
+   let kind = LogKind::from_path(path)
+    .ok_or_else(|| anyhow!("ログファイルの種類が分かりません: {}", path.display()))?;
+   files.push((kind, path.clone()));
+   continue;
+  }
+  let sub_directories: Vec<_> = vec![path.join("log_ngs"), path.join("log")]
+   .into_iter()
+   .filter(|d| d.is_dir())
+   .collect();
+  let directories = match sub_directories.is_empty() {
+   true => vec![path.clone()],
+   false => sub_directories,
+  };
+  for directory in directories {
+   let mut entries: Vec<_> = fs::read_dir(&directory)?
+    .filter_map(|entry| entry.ok())
+    .map(|entry| entry.path())
+    .collect();
+   entries.sort();
+   files.extend(
+    entries
+     .into_iter()
+     .filter_map(|path| LogKind::from_path(&path).map(|kind| (kind, path))),
+   );
+  }
+ }
+ Ok(files)
+}
+
+/// ログファイル全体を解析します。解析できない行は表示して読み飛ばします
+async fn read_log_file(kind: LogKind, path: PathBuf) -> Result<Vec<NgsLog>> {
+ let directory = path.parent().map_or(PathBuf::new(), Path::to_path_buf);
+ let mut file = ActiveLogFile::new(kind, directory, LogTailer::new(path));
+ // リプレイでは追記を待たないため、改行で終わっていない最後の行も読み込みます
+ file.read_remaining_logs().await
+}
+
+#[cfg(test)]
+mod tests {
+ use super::*;
+
+ #[tokio::test]
+ async fn reads_last_line_without_trailing_newline() {
+  let path = std::env::temp_dir().join(format!(
+   "ngs-log-action-replay-{}-ActionLog20210819_00.txt",
+   std::process::id()
+  ));
+  fs::write(
+   &path,
+   "2021-08-19T20:40:17\t243\t[Pickup]\t15161621\tL,A.M.\tN-グラインダー\tNum(1)\r\n\
+    2021-08-19T20:40:56\t249\t[Pickup]\t15161621\tL,A.M.\tツヴィアアーマ",
+  )
+  .unwrap();
+  let ngs_logs = read_log_file(LogKind::Action, path.clone()).await.unwrap();
+  let items: Vec<_> = ngs_logs.iter().map(|l| l.get_body_or_item()).collect();
+  assert_eq!(items, vec!["N-グラインダー", "ツヴィアアーマ"]);
+  fs::remove_file(&path).unwrap();
+ }
+
+ #[test]
+ fn collects_log_files_from_log_root_and_files() {
+  let log_root = std::env::temp_dir().join(format!("ngs-log-action-replay-{}", std::process::id()));
+  let _ = fs::remove_dir_all(&log_root);
+  fs::create_dir_all(log_root.join("log_ngs")).unwrap();
+  fs::create_dir_all(log_root.join("log")).unwrap();
+  for path in &[
+   "log_ngs/ChatLog20210819_00.txt",
+   "log_ngs/ActionLog20210819_00.txt",
+   "log_ngs/readme.txt",
+   "log/RewardLog20210819_00.txt",
+  ] {
+   fs::write(log_root.join(path), "").unwrap();
+  }
+  let kinds: Vec<_> = collect_log_files(std::slice::from_ref(&log_root))
+   .unwrap()
+   .into_iter()
+   .map(|(kind, _)| kind)
+   .collect();
+  assert_eq!(kinds, vec![LogKind::Action, LogKind::Chat, LogKind::Reward]);
+
+  let file = log_root.join("log_ngs").join("ChatLog20210819_00.txt");
+  assert_eq!(collect_log_files(&[file]).unwrap().len(), 1);
+  assert!(collect_log_files(&[log_root.join("log_ngs").join("readme.txt")]).is_err());
+  fs::remove_dir_all(&log_root).unwrap();
+ }
+}
@@ -0,0 +1,490 @@
+use crate::action::Counter;
+use crate::conf::{Conf, If, ItemCount};
+use crate::error::NgsLogActionError;
+use crate::filter::{
+ compile_duration, compile_hour_ranges, compile_keywords, compile_regex, compile_weekdays,
+ find_failing_schedule, invalid_rule, Filter, HourRange,
+};
+use crate::ngs_log::NgsLog;
+use crate::rate_limit::{LimitScope, RateLimiter};
+use crate::template::Variables;
+use crate::window::{BurstDetector, SequenceTracker};
+use aho_corasick::AhoCorasick;
+use chrono::{Duration, Weekday};
+use once_cell::sync::OnceCell;
+use regex::Regex;
+
+static RULES: OnceCell<Vec<Rule<'static>>> = OnceCell::new();
+
+/// conf.toml の [[if]] からルールを構築します。不正な正規表現などがあれば起動時にエラーにします
+pub fn initialize(conf: &'static Conf) -> Result<(), NgsLogActionError> {
+ let rules = compile(conf)?;
+ let _ = RULES.set(rules);
+ Ok(())
+}
+
+/// 構築済みのルール（未構築の場合は空）
+pub fn rules() -> &'static [Rule<'static>] {
+ RULES.get().map_or(&[], Vec::as_slice)
+}
+
+fn compile(conf: &Conf) -> Result<Vec<Rule<'_>>, NgsLogActionError> {
+ conf
+  .r#if
+  .iter()
+  .flatten()
+  .enumerate()
+  .map(|(index, r#if)| Rule::compile(index, r#if))
+  .collect()
+}
+
+/// 正規表現とキーワードの検索を事前に構築した [[if]]
+pub struct Rule<'a> {
+ pub r#if: &'a If,
+ keywords: Option<AhoCorasick>,
+ regex: Option<Regex>,
+ ignore_keywords: Option<AhoCorasick>,
+ ignore_regex: Option<Regex>,
+ item_counts: Option<Vec<ItemCountRule>>,
+ when: Option<Filter<'a>>,
+ active_hours: Option<Vec<HourRange>>,
+ weekdays: Option<Vec<Weekday>>,
+ rate_limiter: Option<RateLimiter>,
+ burst: Option<BurstDetector>,
+ sequence: Option<SequenceTracker<'a>>,
+}
+
+/// 事前に構築した item_counts の1つの条件
+struct ItemCountRule {
+ keywords: Option<AhoCorasick>,
+ regex: Option<Regex>,
+ every: Option<u32>,
+}
+
+impl ItemCountRule {
+ fn compile(index: usize, i: usize, item_count: &ItemCount) -> Result<Self, NgsLogActionError> {
+  Ok(ItemCountRule {
+   keywords: compile_keywords(
+    index,
+    &format!("item_counts #{} の keywords", i + 1),
+    &item_count.keywords,
+   )?,
+   regex: compile_regex(
+    index,
+    &format!("item_counts #{} の regex", i + 1),
+    &item_count.regex,
+   )?,
+   every: item_count.every,
+  })
+ }
+
+ fn matches(&self, item: &str, counter: &Counter) -> bool {
+  if let Some(every) = self.every {
+   if counter.prev / every >= counter.current / every {
+    return false;
+   }
+  }
+  if let Some(ref keywords) = self.keywords {
+   if !keywords.is_match(item) {
+    return false;
+   }
+  }
+  if let Some(ref regex) = self.regex {
+   if !regex.is_match(item) {
+    return false;
+   }
+  }
+  true
+ }
+}
+
+/// regex のグループ名は post のヘッダー名 (ngs-log-action-<名前>) にもなるため、半角英数字と _ に限ります
+fn check_capture_names(index: usize, regex: &Option<Regex>) -> Result<(), NgsLogActionError> {
+ let invalid_name = regex
+  .iter()
+  .flat_map(|regex| regex.capture_names())
+  .flatten()
+  .find(|name| !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'));
+ match invalid_name {
+  Some(name) => Err(invalid_rule(
+   index,
+   "regex".to_string(),
+   format!(
+    "グループ名 {:?} には半角英数字と _ だけを使ってください",
+    name
+   ),
+  )),
+  None => Ok(()),
+ }
+}
+
+impl<'a> Rule<'a> {
+ /// index は conf.toml での [[if]] の順番（0 始まり）です
+ pub fn compile(index: usize, r#if: &'a If) -> Result<Self, NgsLogActionError> {
+  let regex = compile_regex(index, "regex", &r#if.regex)?;
+  check_capture_names(index, &regex)?;
+  let within = compile_duration(index, "within", &r#if.within)?;
+  if within.is_some() && r#if.min_count.is_none() && r#if.sequence.is_none() {
+   return Err(invalid_rule(
+    index,
+    "within".to_string(),
+    "min_count または sequence と合わせて設定してください",
+   ));
+  }
+  Ok(Rule {
+   r#if,
+   keywords: compile_keywords(index, "keywords", &r#if.keywords)?,
+   regex,
+   ignore_keywords: compile_keywords(index, "ignore_keywords", &r#if.ignore_keywords)?,
+   ignore_regex: compile_regex(index, "ignore_regex", &r#if.ignore_regex)?,
+   item_counts: r#if
+    .item_counts
+    .as_ref()
+    .map(|item_counts| {
+     item_counts
+      .iter()
+      .enumerate()
+      .map(|(i, item_count)| ItemCountRule::compile(index, i, item_count))
+      .collect::<Result<Vec<_>, _>>()
+    })
+    .transpose()?,
+   when: r#if
+    .when
+    .as_ref()
+    .map(|when| Filter::compile(index, "when", when))
+    .transpose()?,
+   active_hours: compile_hour_ranges(index, &r#if.active_hours)?,
+   weekdays: compile_weekdays(index, &r#if.weekdays)?,
+   rate_limiter: match (
+    compile_duration(index, "cooldown", &r#if.cooldown)?,
+    r#if.max_per_minute,
+   ) {
+    (_, Some(0)) => {
+     return Err(invalid_rule(
+      index,
+      "max_per_minute".to_string(),
+      "1 以上を設定してください",
+     ))
+    }
+    (None, None) => None,
+    (cooldown, max_per_minute) => Some(RateLimiter::new(cooldown, max_per_minute)),
+   },
+   burst: match (within, r#if.min_count) {
+    (_, Some(0)) => {
+     return Err(invalid_rule(
+      index,
+      "min_count".to_string(),
+      "1 以上を設定してください",
+     ))
+    }
+    (None, Some(_)) => {
+     return Err(invalid_rule(
+      index,
+      "within".to_string(),
+      "min_count と合わせて設定してください",
+     ))
+    }
+    (Some(within), Some(min_count)) => Some(BurstDetector::new(
+     within,
+     min_count as usize,
+     r#if.distinct_names == Some(true),
+     compile_duration(index, "quiet", &r#if.quiet)?.unwrap_or(within),
+    )),
+    (_, None) => None,
+   },
+   sequence: r#if
+    .sequence
+    .as_ref()
+    .map(|sequence| {
+     sequence
+      .iter()
+      .enumerate()
+      .map(|(i, step)| Filter::compile(index, &format!("sequence #{}", i + 1), step))
+      .collect::<Result<Vec<_>, _>>()
+      .map(|steps| SequenceTracker::new(steps, within))
+    })
+    .transpose()?,
+  })
+ }
+
+ /// sequence が設定されている場合はログを記録し、発動する場合は最初の条件からの経過時間を返します
+ pub fn record_sequence(&self, ngs_log: &NgsLog) -> Option<Option<Duration>> {
+  self
+   .sequence
+   .as_ref()
+   .map(|sequence| sequence.record(ngs_log))
+ }
+
+ /// min_count が設定されている場合は条件を満たしたログとして記録し、発動しない場合はその理由の設定項目名を返します
+ pub fn record_burst(&self, ngs_log: &NgsLog) -> Option<&'static str> {
+  self
+   .burst
+   .as_ref()?
+   .record(ngs_log.get_name(), ngs_log.get_datetime())
+ }
+
+ /// cooldown と max_per_minute で発動を制限した場合は、その設定項目名と制限した回数を返します
+ pub fn limit(&self, ngs_log: &NgsLog) -> Option<(&'static str, u64)> {
+  let rate_limiter = self.rate_limiter.as_ref()?;
+  // キーワードごとの場合、 keywords が無いか一致しないログは1つにまとめて数えます
+  let key = match self.r#if.limit_scope.unwrap_or_default() {
+   LimitScope::Global => "",
+   LimitScope::Name => ngs_log.get_name(),
+   LimitScope::Keyword => self.find_matched_keyword(ngs_log).unwrap_or(""),
+  };
+  rate_limiter
+   .acquire(key, ngs_log.get_datetime())
+   .map(|limit| (limit, rate_limiter.suppressed_count()))
+ }
+
+ /// regex のキャプチャを {1} や {boss} のような変数として追加します。一致しなかったグループは空文字列です
+ pub fn insert_captures(&self, ngs_log: &NgsLog, variables: &mut Variables) {
+  if let Some(ref regex) = self.regex {
+   if let Some(captures) = regex.captures(ngs_log.get_body_or_item()) {
+    for (i, name) in regex.capture_names().enumerate() {
+     let value = captures.get(i).map_or("", |m| m.as_str());
+     variables.insert(i.to_string(), value);
+     if let Some(name) = name {
+      variables.insert(name, value);
+     }
+    }
+   }
+  }
+ }
+
+ fn find_matched_keyword(&self, ngs_log: &NgsLog) -> Option<&str> {
+  let keywords = self.r#if.keywords.as_ref()?;
+  let found = self.keywords.as_ref()?.find(ngs_log.get_body_or_item())?;
+  Some(&keywords[found.pattern().as_usize()])
+ }
+
+ /// 集計中のアイテムのいずれかが item_counts の条件を満たすかを返します（ item_counts 未設定の場合は true ）
+ pub fn matches_item_counts<'c>(
+  &self,
+  mut item_counter: impl Iterator<Item = (&'c String, &'c Counter)>,
+ ) -> bool {
+  match self.item_counts {
+   Some(ref item_counts) => item_counter.any(|(item, counter)| {
+    item_counts
+     .iter()
+     .any(|item_count| item_count.matches(item, counter))
+   }),
+   None => true,
+  }
+ }
+
+ /// [[if]] の条件のうち、最初に満たさなかった条件の設定項目名を返します（ item_counts を除く）
+ pub fn find_failing_condition(&self, ngs_log: &NgsLog) -> Option<&'static str> {
+  let r#if = self.r#if;
+  // filters
+  if let Some(ref target) = r#if.target {
+   if !target.matches(ngs_log) {
+    return Some("target");
+   }
+  }
+  if let Some(condition) = find_failing_schedule(
+   self.active_hours.as_deref(),
+   self.weekdays.as_deref(),
+   &ngs_log.get_datetime().naive_local(),
+  ) {
+   return Some(condition);
+  }
+  // categories 未指定の場合は既存の設定の動作を変えないよう入手したアイテムのログのみ対象にします
+  match (&r#if.categories, ngs_log.get_category()) {
+   (Some(categories), Some(category)) if !categories.contains(category) => {
+    return Some("categories")
+   }
+   (Some(_), None) => return Some("categories"),
+   (None, Some(category)) if !category.is_acquisition() => return Some("categories"),
+   _ => {}
+  }
+  // アイテムの属性や Lv を条件にする場合はそれらを持たないログは対象外です
+  let item_log = ngs_log.get_item_log();
+  if let Some(min_level) = r#if.min_level {
+   if item_log
+    .and_then(|l| l.level)
+    .is_none_or(|level| level < min_level)
+   {
+    return Some("min_level");
+   }
+  }
+  if let Some(max_level) = r#if.max_level {
+   if item_log
+    .and_then(|l| l.level)
+    .is_none_or(|level| level > max_level)
+   {
+    return Some("max_level");
+   }
+  }
+  if let Some(ref attributes) = r#if.attributes {
+   let element = item_log
+    .and_then(|l| l.attribute.as_ref())
+    .map(|a| &a.element);
+   if !element.is_some_and(|element| attributes.contains(element)) {
+    return Some("attributes");
+   }
+  }
+  if let Some(ref destinations) = r#if.destinations {
+   let destination = item_log.and_then(|l| l.destination.as_ref());
+   if !destination.is_some_and(|destination| destinations.contains(destination)) {
+    return Some("destinations");
+   }
+  }
+  if let Some(ref channels) = r#if.channels {
+   if let Some(channel) = ngs_log.get_channel() {
+    if !channels.contains(channel) {
+     return Some("channels");
+    }
+   }
+  }
+  if let Some(ref player_ids) = r#if.player_ids {
+   if !player_ids.contains(&ngs_log.get_player_id()) {
+    return Some("player_ids");
+   }
+  }
+  if let Some(ref names) = r#if.names {
+   let name = ngs_log.get_name();
+   if !names.contains(name) {
+    return Some("names");
+   }
+  }
+  if let Some(ref keywords) = self.keywords {
+   if !keywords.is_match(ngs_log.get_body_or_item()) {
+    return Some("keywords");
+   }
+  }
+  if let Some(ref regex) = self.regex {
+   if !regex.is_match(ngs_log.get_body_or_item()) {
+    return Some("regex");
+   }
+  }
+  if let Some(ref when) = self.when {
+   if !when.matches(ngs_log) {
+    return Some("when");
+   }
+  }
+
+  // ignore- series
+  if let Some(ref ignore_names) = r#if.ignore_names {
+   if ignore_names.contains(ngs_log.get_name()) {
+    return Some("ignore_names");
+   }
+  }
+  if let Some(ref ignore_keywords) = self.ignore_keywords {
+   if ignore_keywords.is_match(ngs_log.get_body_or_item()) {
+    return Some("ignore_keywords");
+   }
+  }
+  if let Some(ref ignore_regex) = self.ignore_regex {
+   if ignore_regex.is_match(ngs_log.get_body_or_item()) {
+    return Some("ignore_regex");
+   }
+  }
+  None
+ }
+}
+
+#[cfg(test)]
+mod tests {
+ use super::*;
+ use crate::ngs_log::{chat_log, NgsLogChannel};
+
+ #[test]
+ fn invalid_patterns_are_reported_with_rule_index_and_field() {
+  let conf: Conf = toml::from_str(
+   r#"
+[[if]]
+regex = "ok"
+[[if]]
+item_counts = [{regex = "ok"}, {regex = "(unclosed"}]
+"#,
+  )
+  .unwrap();
+  match compile(&conf) {
+   Err(NgsLogActionError::InvalidRule { index, field, .. }) => {
+    assert_eq!(index, 2);
+    assert_eq!(field, "item_counts #2 の regex");
+   }
+   other => panic!("unexpected result: {:?}", other.map(|rules| rules.len())),
+  }
+ }
+
+ #[test]
+ fn within_requires_min_count_or_sequence() {
+  let compile_within = |fields: &str| {
+   let conf: Conf = toml::from_str(&format!("[[if]]\nwithin = \"2m\"\n{}", fields)).unwrap();
+   compile(&conf).map(|rules| rules.len())
+  };
+  assert!(compile_within("min_count = 5").is_ok());
+  assert!(compile_within("sequence = [{keywords = [\"a\"]}, {keywords = [\"b\"]}]").is_ok());
+  match compile_within("") {
+   Err(NgsLogActionError::InvalidRule { index, field, .. }) => {
+    assert_eq!(index, 1);
+    assert_eq!(field, "within");
+   }
+   other => panic!("unexpected result: {:?}", other),
+  }
+ }
+
+ #[test]
+ fn compiled_rule_reports_first_failing_condition() {
+  let conf: Conf = toml::from_str(
+   r#"
+[[if]]
+keywords = ["雷雨", "ラッピー"]
+regex = "発生"
+ignore_keywords = ["終了"]
+"#,
+  )
+  .unwrap();
+  let rules = compile(&conf).unwrap();
+  let rule = &rules[0];
+  assert_eq!(
+   rule.find_failing_condition(&chat_log(NgsLogChannel::Guild, "A", "雷雨が発生")),
+   None
+  );
+  assert_eq!(
+   rule.find_failing_condition(&chat_log(NgsLogChannel::Guild, "A", "晴れ")),
+   Some("keywords")
+  );
+  assert_eq!(
+   rule.find_failing_condition(&chat_log(NgsLogChannel::Guild, "A", "ラッピー")),
+   Some("regex")
+  );
+  assert_eq!(
+   rule.find_failing_condition(&chat_log(NgsLogChannel::Guild, "A", "雷雨の発生が終了")),
+   Some("ignore_keywords")
+  );
+ }
+
+ #[test]
+ fn regex_captures_become_variables() {
+  let conf: Conf = toml::from_str(
+   r#"
+[[if]]
+regex = "〘緊急警報発令〙(?P<boss>\\S+)(討伐)?"
+"#,
+  )
+  .unwrap();
+  let rules = compile(&conf).unwrap();
+  let ngs_log = chat_log(NgsLogChannel::Guild, "A", "〘緊急警報発令〙ネクス・ヴェラ");
+  let mut variables = Variables::from_log(&ngs_log);
+  rules[0].insert_captures(&ngs_log, &mut variables);
+  assert_eq!(
+   variables.render_plain("{boss} / {1} / [{2}] / {name}"),
+   "ネクス・ヴェラ / ネクス・ヴェラ / [] / A"
+  );
+
+  let conf: Conf = toml::from_str(
+   r#"
+[[if]]
+regex = "(?P<ボス>\\S+)討伐"
+"#,
+  )
+  .unwrap();
+  match compile(&conf) {
+   Err(NgsLogActionError::InvalidRule { field, .. }) => assert_eq!(field, "regex"),
+   other => panic!("unexpected result: {:?}", other.map(|rules| rules.len())),
+  }
+ }
+}
@@ -0,0 +1,337 @@
+use anyhow::Result;
+use chrono::{DateTime, FixedOffset};
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
+use std::{
+ fs::{self, File},
+ io::{self, Read, Seek, SeekFrom},
+ path::{Path, PathBuf},
+ time::SystemTime,
+};
+
+/// ログファイル上の行とその行頭のバイト位置、行番号（1始まり）
+#[derive(Debug, PartialEq, Eq)]
+pub struct TailedLine {
+ pub position: u64,
+ pub line_number: u64,
+ pub text: String,
+}
+
+/// ファイルごとに最後に配信したログの位置を示すカーソル
+///
+/// 日時だけでは同じ秒に書かれた行を区別できないため log_id と行頭のバイト位置を組み合わせます。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogCursor {
+ pub datetime: DateTime<FixedOffset>,
+ pub log_id: u16,
+ pub position: u64,
+}
+
+impl LogCursor {
+ /// カーソルより後ろのログ（未配信のログ）か判定します
+ pub fn is_behind(&self, datetime: &DateTime<FixedOffset>, log_id: u16, position: u64) -> bool {
+  match (datetime, log_id).cmp(&(&self.datetime, self.log_id)) {
+   std::cmp::Ordering::Equal => position > self.position,
+   ordering => ordering.is_gt(),
+  }
+ }
+}
+
+/// ログファイルの追記分だけを読み出すテイラー
+///
+/// 読み込み済みのバイト位置と BOM から判定したエンコーディングを覚えておき、
+/// ポーリングの度にファイル全体をデコードし直さずに済むようにします。
+/// 改行で終わっていない書きかけの行は次回の読み込みまで保留します。
+pub struct LogTailer {
+ path: PathBuf,
+ /// ファイルから読み込み済みのバイト位置
+ offset: u64,
+ /// 読み込み済みの行数
+ line_count: u64,
+ /// BOM から判定したエンコーディング（未判定なら None）
+ encoding: Option<&'static Encoding>,
+ /// 改行で終わっていない読みかけの行のバイト列
+ pending: Vec<u8>,
+ /// 最初の改行までを読み捨てるか（ファイルの途中から読み始めた場合）
+ skip_partial_line: bool,
+ /// ファイルの作成日時（ファイルの置き換え検出用）
+ created: Option<SystemTime>,
+ /// 最後に配信したログのカーソル（ファイルを読み直しても維持します）
+ cursor: Option<LogCursor>,
+}
+
+impl LogTailer {
+ /// ファイルの先頭から読み込むテイラーを作成します
+ pub fn new(path: PathBuf) -> Self {
+  Self {
+   path,
+   offset: 0,
+   line_count: 0,
+   encoding: None,
+   pending: Vec::new(),
+   skip_partial_line: false,
+   created: None,
+   cursor: None,
+  }
+ }
+
+ /// 既存の内容は読み飛ばし、以降に追記された行だけを読み込むテイラーを作成します
+ pub fn new_at_end(path: PathBuf) -> Result<Self> {
+  let mut tailer = Self::new(path);
+  let metadata = fs::metadata(&tailer.path)?;
+  // 行番号を数えるために既存の内容を固定長のバッファで読み流します（デコードはしません）
+  let mut file = File::open(&tailer.path)?;
+  let mut head = Vec::new();
+  file.by_ref().take(3).read_to_end(&mut head)?;
+  let (encoding, bom_length) = Encoding::for_bom(&head).unwrap_or((UTF_8, 0));
+  let (newline, unit) = newline_of(encoding);
+  let mut buffer = [0; 8192];
+  // コードユニットの途中で読み込みが区切られた場合の残りのバイト列
+  let mut pending = head[bom_length..].to_vec();
+  let mut offset = bom_length as u64;
+  let mut ends_with_newline = true;
+  loop {
+   let complete_length = pending.len() / unit * unit;
+   for chunk in pending[..complete_length].chunks_exact(unit) {
+    ends_with_newline = chunk == newline;
+    if ends_with_newline {
+     tailer.line_count += 1;
+    }
+   }
+   offset += complete_length as u64;
+   pending.drain(..complete_length);
+   let read_length = file.read(&mut buffer)?;
+   if read_length == 0 {
+    break;
+   }
+   pending.extend_from_slice(&buffer[..read_length]);
+  }
+  if !pending.is_empty() {
+   ends_with_newline = false;
+   offset += pending.len() as u64;
+  }
+  tailer.skip_partial_line = !ends_with_newline;
+  tailer.offset = offset;
+  tailer.encoding = Some(encoding);
+  tailer.created = metadata.created().ok();
+  Ok(tailer)
+ }
+
+ pub fn path(&self) -> &Path {
+  &self.path
+ }
+
+ pub fn cursor_mut(&mut self) -> &mut Option<LogCursor> {
+  &mut self.cursor
+ }
+
+ /// 前回から追記された完結した行を読み込みます
+ ///
+ /// ファイルが切り詰められた、または別のファイルに置き換えられた場合は先頭から読み直します。
+ pub fn read_lines(&mut self) -> Result<Vec<TailedLine>> {
+  let metadata = match fs::metadata(&self.path) {
+   Ok(metadata) => metadata,
+   Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+   Err(e) => return Err(e.into()),
+  };
+  let created = metadata.created().ok();
+  let replaced = self.created.is_some() && created.is_some() && self.created != created;
+  if metadata.len() < self.offset || replaced {
+   self.reset();
+  }
+  self.created = created;
+  if metadata.len() == self.offset {
+   return Ok(Vec::new());
+  }
+
+  let mut file = File::open(&self.path)?;
+  file.seek(SeekFrom::Start(self.offset))?;
+  let read_length = file.read_to_end(&mut self.pending)?;
+  self.offset += read_length as u64;
+
+  let encoding = match self.encoding {
+   Some(encoding) => encoding,
+   None => match self.detect_encoding() {
+    Some(encoding) => encoding,
+    // BOM の判定に必要なバイト数がまだ書き込まれていない
+    None => return Ok(Vec::new()),
+   },
+  };
+
+  let (newline, unit) = newline_of(encoding);
+  let pending_position = self.offset - self.pending.len() as u64;
+  let mut lines = Vec::new();
+  let mut begin = 0;
+  let mut i = 0;
+  while i + unit <= self.pending.len() {
+   if &self.pending[i..i + unit] == newline {
+    self.line_count += 1;
+    if self.skip_partial_line {
+     self.skip_partial_line = false;
+    } else {
+     let (line, _) = encoding.decode_without_bom_handling(&self.pending[begin..i]);
+     lines.push(TailedLine {
+      position: pending_position + begin as u64,
+      line_number: self.line_count,
+      text: line.trim_end_matches('\r').to_string(),
+     });
+    }
+    begin = i + unit;
+   }
+   i += unit;
+  }
+  self.pending.drain(..begin);
+
+  Ok(lines)
+ }
+
+ /// ファイルの最後まで読み込みます。改行で終わっていない最後の行も1行として返します
+ ///
+ /// 追記を待たずにファイルを1度だけ読み切るリプレイで使います。
+ pub fn read_to_end(&mut self) -> Result<Vec<TailedLine>> {
+  let mut lines = self.read_lines()?;
+  if let Some(encoding) = self.encoding {
+   if !self.pending.is_empty() && !self.skip_partial_line {
+    self.line_count += 1;
+    let (line, _) = encoding.decode_without_bom_handling(&self.pending);
+    lines.push(TailedLine {
+     position: self.offset - self.pending.len() as u64,
+     line_number: self.line_count,
+     text: line.trim_end_matches('\r').to_string(),
+    });
+   }
+   self.pending.clear();
+  }
+  Ok(lines)
+ }
+
+ fn reset(&mut self) {
+  self.offset = 0;
+  self.line_count = 0;
+  self.encoding = None;
+  self.pending.clear();
+  self.skip_partial_line = false;
+ }
+
+ /// 読み込んだ先頭のバイト列から BOM を判定し、 BOM を取り除きます
+ fn detect_encoding(&mut self) -> Option<&'static Encoding> {
+  const UTF_8_BOM: [u8; 3] = [0xEF, 0xBB, 0xBF];
+  let (encoding, bom_length) = match Encoding::for_bom(&self.pending) {
+   Some(detected) => detected,
+   None if self.pending.len() < 2 => return None,
+   None if self.pending.len() < 3 && UTF_8_BOM.starts_with(&self.pending) => return None,
+   None => (UTF_8, 0),
+  };
+  self.pending.drain(..bom_length);
+  self.encoding = Some(encoding);
+  Some(encoding)
+ }
+}
+
+/// エンコーディングごとの改行のバイト列とコードユニットのバイト数
+fn newline_of(encoding: &'static Encoding) -> (&'static [u8], usize) {
+ if encoding == UTF_16LE {
+  (&[0x0A, 0x00], 2)
+ } else if encoding == UTF_16BE {
+  (&[0x00, 0x0A], 2)
+ } else {
+  (&[0x0A], 1)
+ }
+}
+
+#[cfg(test)]
+mod tests {
+ use super::*;
+ use std::io::Write;
+
+ fn temp_log_path(name: &str) -> PathBuf {
+  let path = std::env::temp_dir().join(format!(
+   "ngs-log-action-tailer-{}-{}.txt",
+   std::process::id(),
+   name
+  ));
+  let _ = fs::remove_file(&path);
+  path
+ }
+
+ fn texts(lines: Vec<TailedLine>) -> Vec<String> {
+  lines.into_iter().map(|l| l.text).collect()
+ }
+
+ fn append_utf16le(path: &Path, s: &str, with_bom: bool) {
+  let mut file = fs::OpenOptions::new()
+   .create(true)
+   .append(true)
+   .open(path)
+   .unwrap();
+  if with_bom {
+   file.write_all(&[0xFF, 0xFE]).unwrap();
+  }
+  let bytes: Vec<u8> = s.encode_utf16().flat_map(|u| u.to_le_bytes()).collect();
+  file.write_all(&bytes).unwrap();
+ }
+
+ #[test]
+ fn reads_only_appended_complete_lines() {
+  let path = temp_log_path("append");
+  append_utf16le(&path, "line1\r\nli", true);
+  let mut tailer = LogTailer::new(path.clone());
+  assert_eq!(texts(tailer.read_lines().unwrap()), vec!["line1"]);
+  assert!(tailer.read_lines().unwrap().is_empty());
+  append_utf16le(&path, "ne2\r\nline3\r\n", false);
+  let lines = tailer.read_lines().unwrap();
+  // BOM 2 bytes + "line1\r\n" 14 bytes
+  assert_eq!(lines[0].position, 16);
+  assert_eq!(lines[0].line_number, 2);
+  assert_eq!(texts(lines), vec!["line2", "line3"]);
+  fs::remove_file(&path).unwrap();
+ }
+
+ #[test]
+ fn skips_existing_content_when_started_at_end() {
+  let path = temp_log_path("at-end");
+  append_utf16le(&path, "old\r\nold-partial", true);
+  let mut tailer = LogTailer::new_at_end(path.clone()).unwrap();
+  append_utf16le(&path, "-rest\r\nnew\r\n", false);
+  let lines = tailer.read_lines().unwrap();
+  assert_eq!(lines[0].line_number, 3);
+  assert_eq!(texts(lines), vec!["new"]);
+  fs::remove_file(&path).unwrap();
+ }
+
+ #[test]
+ fn counts_lines_beyond_the_read_buffer_when_started_at_end() {
+  let path = temp_log_path("at-end-large");
+  append_utf16le(&path, &"line\r\n".repeat(1000), true);
+  let mut tailer = LogTailer::new_at_end(path.clone()).unwrap();
+  append_utf16le(&path, "new\r\n", false);
+  let lines = tailer.read_lines().unwrap();
+  assert_eq!(lines[0].line_number, 1001);
+  assert_eq!(lines[0].position, 2 + 12 * 1000);
+  assert_eq!(texts(lines), vec!["new"]);
+  fs::remove_file(&path).unwrap();
+ }
+
+ #[test]
+ fn read_to_end_returns_last_line_without_newline() {
+  let path = temp_log_path("to-end");
+  append_utf16le(&path, "first\r\nlast", true);
+  let mut tailer = LogTailer::new(path.clone());
+  let lines = tailer.read_to_end().unwrap();
+  assert_eq!(lines[1].position, 2 + 14);
+  assert_eq!(lines[1].line_number, 2);
+  assert_eq!(texts(lines), vec!["first", "last"]);
+  fs::remove_file(&path).unwrap();
+ }
+
+ #[test]
+ fn rereads_truncated_file_from_the_beginning() {
+  let path = temp_log_path("truncate");
+  append_utf16le(&path, "first\r\nsecond\r\n", true);
+  let mut tailer = LogTailer::new(path.clone());
+  assert_eq!(texts(tailer.read_lines().unwrap()), vec!["first", "second"]);
+  fs::remove_file(&path).unwrap();
+  append_utf16le(&path, "x\r\n", true);
+  assert_eq!(texts(tailer.read_lines().unwrap()), vec!["x"]);
+  fs::remove_file(&path).unwrap();
+ }
+}
@@ -0,0 +1,183 @@
+use crate::ngs_log::NgsLog;
+use chrono::Duration;
+use std::borrow::Cow;
+
+/// アイテムのログの変数（チャットログや値の無い項目は空文字列になります）
+const ITEM_VARIABLES: [&str; 8] = [
+ "item",
+ "count",
+ "level",
+ "attribute",
+ "attribute_value",
+ "current_meseta",
+ "meseta",
+ "destination",
+];
+
+/// アクションの設定に {name} のように埋め込める変数
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Variables {
+ /// ログの内容の変数
+ log: Vec<(String, String)>,
+ /// [[if]] の評価で求めた変数（ sequence の経過時間や regex のキャプチャなど）。
+ /// ログの内容の変数と同じ名前の場合はこちらを優先します
+ extra: Vec<(String, String)>,
+}
+
+impl Variables {
+ pub fn from_log(ngs_log: &NgsLog) -> Self {
+  let mut log = vec![
+   ("body".to_string(), ngs_log.get_body_or_item_with_count()),
+   ("name".to_string(), ngs_log.get_name().clone()),
+   (
+    "channel".to_string(),
+    ngs_log
+     .get_channel()
+     .map_or("ITEM".to_string(), |c| format!("{:?}", c)),
+   ),
+   (
+    "datetime".to_string(),
+    format!("{:?}", ngs_log.get_datetime()),
+   ),
+  ];
+  let properties = ngs_log
+   .get_item_log()
+   .map_or(Vec::new(), |l| l.get_properties());
+  for variable in ITEM_VARIABLES.iter() {
+   let value = properties
+    .iter()
+    .find(|(key, _)| key == variable)
+    .map_or(String::new(), |(_, value)| value.clone());
+   log.push((variable.to_string(), value));
+  }
+  Variables {
+   log,
+   extra: Vec::new(),
+  }
+ }
+
+ /// [[if]] の評価で求めた変数を追加します
+ pub fn insert(&mut self, key: impl Into<String>, value: impl Into<String>) {
+  self.extra.push((key.into(), value.into()));
+ }
+
+ /// sequence の最初のログからの経過時間を {elapsed} ( 1:02:03 ) と {elapsed_seconds} ( 3723 ) として追加します
+ pub fn insert_elapsed(&mut self, elapsed: Duration) {
+  self.insert("elapsed", format_elapsed(elapsed));
+  self.insert("elapsed_seconds", elapsed.num_seconds().to_string());
+ }
+
+ /// [[if]] の評価で求めた変数
+ pub fn extra(&self) -> &[(String, String)] {
+  &self.extra
+ }
+
+ pub fn get(&self, key: &str) -> Option<&str> {
+  self
+   .extra
+   .iter()
+   .chain(self.log.iter())
+   .find(|(k, _)| k == key)
+   .map(|(_, value)| value.as_str())
+ }
+
+ /// {key} や {key:json} の変数の値。 :json の場合は JSON の文字列の中身としてエスケープします
+ fn get_formatted(&self, key: &str) -> Option<Cow<'_, str>> {
+  match key.split_once(':') {
+   Some((key, "json")) => Some(Cow::from(escape_json(self.get(key)?))),
+   Some(_) => None,
+   None => Some(Cow::from(self.get(key)?)),
+  }
+ }
+
+ /// text の {key} を変数の値に置き換えます
+ pub fn render_plain(&self, text: &str) -> String {
+  self.render(text, |value| Cow::Borrowed(value))
+ }
+
+ /// text の {key} を変数の値を encode で変換した文字列に置き換えます。
+ /// 変数の無い {key} はそのまま残します
+ pub fn render(&self, text: &str, encode: impl Fn(&str) -> Cow<'_, str>) -> String {
+  let mut rendered = String::with_capacity(text.len());
+  let mut rest = text;
+  while let Some(start) = rest.find('{') {
+   rendered.push_str(&rest[..start]);
+   rest = &rest[start..];
+   match rest
+    .find('}')
+    .and_then(|end| Some((end, self.get_formatted(&rest[1..end])?)))
+   {
+    Some((end, value)) => {
+     rendered.push_str(&encode(&value));
+     rest = &rest[end + 1..];
+    }
+    None => {
+     rendered.push('{');
+     rest = &rest[1..];
+    }
+   }
+  }
+  rendered.push_str(rest);
+  rendered
+ }
+}
+
+/// JSON の文字列の中身として使えるように " や \ 、改行などの制御文字をエスケープします
+fn escape_json(value: &str) -> String {
+ let mut escaped = String::with_capacity(value.len());
+ for c in value.chars() {
+  match c {
+   '"' => escaped.push_str("\\\""),
+   '\\' => escaped.push_str("\\\\"),
+   '\n' => escaped.push_str("\\n"),
+   '\r' => escaped.push_str("\\r"),
+   '\t' => escaped.push_str("\\t"),
+   c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+   c => escaped.push(c),
+  }
+ }
+ escaped
+}
+
+/// 経過時間を 12:34 や 1:02:03 の形式にします
+pub fn format_elapsed(elapsed: Duration) -> String {
+ let seconds = elapsed.num_seconds().max(0);
+ match seconds / 3600 {
+  0 => format!("{}:{:02}", seconds / 60, seconds % 60),
+  hours => format!("{}:{:02}:{:02}", hours, seconds / 60 % 60, seconds % 60),
+ }
+}
+
+#[cfg(test)]
+mod tests {
+ use super::*;
+ use crate::ngs_log::{chat_log, NgsLogChannel};
+
+ #[test]
+ fn render_replaces_known_variables_only_once() {
+  let mut variables = Variables::default();
+  variables.insert("name", "{elapsed}");
+  variables.insert_elapsed(Duration::seconds(754));
+  assert_eq!(
+   variables.render_plain("{name} {elapsed} {elapsed_seconds} {unknown} {"),
+   "{elapsed} 12:34 754 {unknown} {"
+  );
+  variables.insert("quote", "a\"b\nc");
+  assert_eq!(
+   variables.render_plain("{quote:json} {quote:xml}"),
+   "a\\\"b\\nc {quote:xml}"
+  );
+  assert_eq!(format_elapsed(Duration::seconds(3723)), "1:02:03");
+ }
+
+ #[test]
+ fn channel_is_rendered_without_quotes() {
+  let ngs_log = chat_log(NgsLogChannel::Party, "L,A.M.", "hi");
+  let variables = Variables::from_log(&ngs_log);
+  assert_eq!(variables.get("channel"), Some("Party"));
+  assert_eq!(
+   variables.render_plain(r#"{"channel": "{channel:json}"}"#),
+   r#"{"channel": "Party"}"#
+  );
+ }
+}
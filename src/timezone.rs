@@ -0,0 +1,170 @@
+use anyhow::Result;
+use chrono::{
+ offset::{LocalResult, Offset},
+ DateTime, FixedOffset, Local, NaiveDateTime, TimeZone, Utc,
+};
+use chrono_tz::Tz;
+use once_cell::sync::OnceCell;
+
+static LOG_TIMEZONE: OnceCell<LogTimezone> = OnceCell::new();
+
+/// ログの日時を解釈するタイムゾーン
+///
+/// ログにはオフセットの無い現地時刻が書き込まれるため、日時ごとにオフセットを求めます。
+/// 起動時のオフセットや Unix エポックのオフセットを使うと夏時間の期間の日時がずれます。
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogTimezone {
+ /// OS のタイムゾーン
+ Local,
+ /// IANA のタイムゾーン名で指定したタイムゾーン 例: Europe/Berlin
+ Iana(Tz),
+}
+
+impl LogTimezone {
+ /// IANA のタイムゾーン名から作成します
+ pub fn from_name(name: &str) -> Result<Self> {
+  let tz = name
+   .parse()
+   .map_err(|e| anyhow::anyhow!("log_timezone {:?} は不明なタイムゾーンです: {}", name, e))?;
+  Ok(LogTimezone::Iana(tz))
+ }
+
+ pub fn now(&self) -> DateTime<FixedOffset> {
+  let now = Utc::now();
+  match self {
+   LogTimezone::Local => fix(now.with_timezone(&Local)),
+   LogTimezone::Iana(tz) => fix(now.with_timezone(tz)),
+  }
+ }
+
+ /// 現地時刻にその日時のオフセットを付けます
+ ///
+ /// 夏時間の終わりで同じ時刻が2回ある場合は早い方の時刻にしますが、
+ /// previous（直前のログの日時）より前になる場合は遅い方の時刻にします。
+ /// 夏時間の始まりで存在しない時刻は切り替わる前のオフセットで解釈します。
+ pub fn localize(
+  &self,
+  naive: NaiveDateTime,
+  previous: Option<&DateTime<FixedOffset>>,
+ ) -> DateTime<FixedOffset> {
+  match self {
+   LogTimezone::Local => localize(&Local, naive, previous),
+   LogTimezone::Iana(tz) => localize(tz, naive, previous),
+  }
+ }
+}
+
+fn fix<T: TimeZone>(datetime: DateTime<T>) -> DateTime<FixedOffset> {
+ let offset = datetime.offset().fix();
+ datetime.with_timezone(&offset)
+}
+
+fn localize<T: TimeZone>(
+ tz: &T,
+ naive: NaiveDateTime,
+ previous: Option<&DateTime<FixedOffset>>,
+) -> DateTime<FixedOffset> {
+ match tz.from_local_datetime(&naive) {
+  LocalResult::Single(datetime) => fix(datetime),
+  LocalResult::Ambiguous(earliest, latest) => {
+   let (earliest, latest) = (fix(earliest), fix(latest));
+   match previous {
+    Some(previous) if earliest < *previous => latest,
+    _ => earliest,
+   }
+  }
+  LocalResult::None => {
+   // 切り替わりより十分前（1日前）のオフセット
+   let offset = tz
+    .offset_from_utc_datetime(&(naive - chrono::Duration::days(1)))
+    .fix();
+   match offset.from_local_datetime(&naive) {
+    LocalResult::Single(datetime) => datetime,
+    _ => fix(Utc.from_utc_datetime(&naive)),
+   }
+  }
+ }
+}
+
+/// ログの日時を解釈するタイムゾーンを設定します（起動時に一度だけ）
+pub fn initialize(log_timezone: LogTimezone) {
+ let _ = LOG_TIMEZONE.set(log_timezone);
+}
+
+/// ログの日時を解釈するタイムゾーン（未設定の場合は OS のタイムゾーン）
+pub fn log_timezone() -> &'static LogTimezone {
+ LOG_TIMEZONE.get_or_init(|| LogTimezone::Local)
+}
+
+/// ログのタイムゾーンでの現在日時
+pub fn now() -> DateTime<FixedOffset> {
+ log_timezone().now()
+}
+
+#[cfg(test)]
+mod tests {
+ use super::*;
+
+ fn naive(s: &str) -> NaiveDateTime {
+  NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S").unwrap()
+ }
+
+ fn localize_to_string(tz: &LogTimezone, s: &str, previous: Option<&str>) -> String {
+  let previous = previous.map(|p| DateTime::parse_from_rfc3339(p).unwrap());
+  tz.localize(naive(s), previous.as_ref()).to_rfc3339()
+ }
+
+ #[test]
+ fn offsets_follow_daylight_saving_time() {
+  let berlin = LogTimezone::from_name("Europe/Berlin").unwrap();
+  assert_eq!(
+   localize_to_string(&berlin, "2021-03-28T01:59:59", None),
+   "2021-03-28T01:59:59+01:00"
+  );
+  assert_eq!(
+   localize_to_string(&berlin, "2021-03-28T03:00:00", None),
+   "2021-03-28T03:00:00+02:00"
+  );
+  // 夏時間の始まりで存在しない時刻
+  assert_eq!(
+   localize_to_string(&berlin, "2021-03-28T02:30:00", None),
+   "2021-03-28T02:30:00+01:00"
+  );
+  assert_eq!(
+   localize_to_string(&berlin, "2021-08-19T20:40:56", None),
+   "2021-08-19T20:40:56+02:00"
+  );
+  let tokyo = LogTimezone::from_name("Asia/Tokyo").unwrap();
+  assert_eq!(
+   localize_to_string(&tokyo, "2021-08-19T20:40:56", None),
+   "2021-08-19T20:40:56+09:00"
+  );
+  assert!(LogTimezone::from_name("Mars/Olympus_Mons").is_err());
+ }
+
+ #[test]
+ fn repeated_wall_clock_times_do_not_go_backwards() {
+  let new_york = LogTimezone::from_name("America/New_York").unwrap();
+  // 夏時間の終わりの 01:00-02:00 は2回あります
+  assert_eq!(
+   localize_to_string(&new_york, "2021-11-07T01:30:00", None),
+   "2021-11-07T01:30:00-04:00"
+  );
+  assert_eq!(
+   localize_to_string(
+    &new_york,
+    "2021-11-07T01:10:00",
+    Some("2021-11-07T01:59:00-04:00")
+   ),
+   "2021-11-07T01:10:00-05:00"
+  );
+  assert_eq!(
+   localize_to_string(
+    &new_york,
+    "2021-11-07T01:40:00",
+    Some("2021-11-07T01:30:00-04:00")
+   ),
+   "2021-11-07T01:40:00-04:00"
+  );
+ }
+}
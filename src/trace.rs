@@ -0,0 +1,111 @@
+use crate::conf::ActionType;
+use crate::ngs_log::NgsLog;
+use crate::{format_datetime, CONF};
+use anyhow::Result;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
+
+/// true の場合はログごとに [[if]] の評価結果を表示します ( --explain または [global] trace = true )
+static TRACE_ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn enable() {
+ TRACE_ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+ TRACE_ENABLED.load(Ordering::Relaxed)
+}
+
+/// 1つの [[if]] をログに適用した結果
+#[derive(Debug, PartialEq, Eq)]
+pub enum Evaluation {
+ /// 条件を満たさなかった（最初に満たさなかった条件の設定項目名）
+ Rejected(&'static str),
+ /// 条件を満たしたが発動を制限した（制限した設定項目名と、この [[if]] で制限した回数）
+ Suppressed { limit: &'static str, count: u64 },
+ /// 条件を満たした（先の [[if]] で実行済みのため実行しなかったアクション）
+ Fired { skipped_actions: Vec<ActionType> },
+}
+
+impl Evaluation {
+ fn describe(&self) -> String {
+  match self {
+   Evaluation::Rejected(condition) => format!("不一致 ( {} )", condition),
+   Evaluation::Suppressed { limit, count } => {
+    format!("制限 ( {} 、制限した回数: {} )", limit, count)
+   }
+   Evaluation::Fired { skipped_actions } if skipped_actions.is_empty() => "発動".to_string(),
+   Evaluation::Fired { skipped_actions } => {
+    format!("発動 ( 実行済みのため実行しない: {:?} )", skipped_actions)
+   }
+  }
+ }
+}
+
+/// 評価するログを表示します
+pub fn print_log(ngs_log: &NgsLog) -> Result<()> {
+ let mut stdout = StandardStream::stdout(ColorChoice::Always);
+ let color = Some(Color::Ansi256(CONF.get_color_ansi256_system()));
+ stdout.set_color(ColorSpec::new().set_fg(color))?;
+ writeln!(
+  &mut stdout,
+  "[Trace]{}{} {} {}: {}",
+  CONF.get_column_separator(),
+  format_datetime(ngs_log.get_datetime()),
+  ngs_log.get_channel_or_category_string(),
+  ngs_log.get_name(),
+  ngs_log.get_body_or_item_with_count()
+ )?;
+ Ok(())
+}
+
+/// index 番目（0 始まり）の [[if]] の評価結果を表示します
+pub fn print_evaluation(index: usize, evaluation: &Evaluation) -> Result<()> {
+ let mut stdout = StandardStream::stdout(ColorChoice::Always);
+ let color = Some(Color::Ansi256(CONF.get_color_ansi256_system()));
+ stdout.set_color(ColorSpec::new().set_fg(color))?;
+ writeln!(
+  &mut stdout,
+  "[Trace]{}  [[if]] #{}: {}",
+  CONF.get_column_separator(),
+  index + 1,
+  evaluation.describe()
+ )?;
+ Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+ use super::*;
+
+ #[test]
+ fn evaluations_describe_failing_condition_and_skipped_actions() {
+  assert_eq!(
+   Evaluation::Rejected("keywords").describe(),
+   "不一致 ( keywords )"
+  );
+  assert_eq!(
+   Evaluation::Fired {
+    skipped_actions: vec![]
+   }
+   .describe(),
+   "発動"
+  );
+  assert_eq!(
+   Evaluation::Fired {
+    skipped_actions: vec![ActionType::Show, ActionType::Sound]
+   }
+   .describe(),
+   "発動 ( 実行済みのため実行しない: [Show, Sound] )"
+  );
+  assert_eq!(
+   Evaluation::Suppressed {
+    limit: "cooldown",
+    count: 3
+   }
+   .describe(),
+   "制限 ( cooldown 、制限した回数: 3 )"
+  );
+ }
+}
@@ -0,0 +1,65 @@
+use anyhow::Result;
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::Path;
+use tokio::{
+ sync::mpsc::{unbounded_channel, UnboundedReceiver},
+ time::{sleep, Duration},
+};
+
+/// ログフォルダの変更を OS のファイル通知で待つウォッチャー
+///
+/// 通知を受け取れない環境（ネットワークドライブ等）や通知の取りこぼしに備えて、
+/// 通知が無くても一定間隔で読み込む従来のポーリングも併用します。
+pub struct LogWatcher {
+ /// ファイル通知を使わずにポーリングのみで動作する場合は None
+ watcher: Option<(RecommendedWatcher, UnboundedReceiver<()>)>,
+ polling_interval: Duration,
+}
+
+impl LogWatcher {
+ /// ポーリングのみで動作するウォッチャーを作成します
+ pub fn polling(polling_interval: Duration) -> Self {
+  Self {
+   watcher: None,
+   polling_interval,
+  }
+ }
+
+ /// 存在するフォルダをファイル通知で監視するウォッチャーを作成します
+ pub fn watch(directories: &[&Path], polling_interval: Duration) -> Result<Self> {
+  let (sender, receiver) = unbounded_channel();
+  let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+   // 通知の内容に関わらずログを読み込むので、受信側が終了していても構いません
+   if event.map_or(true, |e| !e.kind.is_access()) {
+    let _ = sender.send(());
+   }
+  })?;
+  for directory in directories.iter().filter(|d| d.is_dir()) {
+   watcher.watch(directory, RecursiveMode::NonRecursive)?;
+  }
+  Ok(Self {
+   watcher: Some((watcher, receiver)),
+   polling_interval,
+  })
+ }
+
+ pub fn is_watching(&self) -> bool {
+  self.watcher.is_some()
+ }
+
+ /// ファイルの変更通知かポーリング間隔の経過まで待ちます
+ pub async fn wait(&mut self) {
+  match self.watcher {
+   Some((_, ref mut receiver)) => {
+    tokio::select! {
+     _ = receiver.recv() => {
+      // 1回の書き込みで届く複数の通知はまとめて1回の読み込みにします
+      while receiver.try_recv().is_ok() {}
+     }
+     _ = sleep(self.polling_interval) => (),
+    }
+   }
+   None => sleep(self.polling_interval).await,
+  }
+ }
+}
@@ -0,0 +1,206 @@
+use crate::filter::Filter;
+use crate::ngs_log::NgsLog;
+use chrono::{DateTime, Duration, FixedOffset};
+use std::collections::{HashSet, VecDeque};
+use std::sync::Mutex;
+
+/// within の期間内に条件を満たしたログが min_count 件に達したら1回だけ発動する集計
+///
+/// 発動した後は、条件を満たすログが quiet の期間途切れるまで発動しません。
+/// 経過時間はログの日時で数えるため、リプレイでも同じように発動します。
+#[derive(Debug)]
+pub struct BurstDetector {
+ within: Duration,
+ min_count: usize,
+ distinct_names: bool,
+ quiet: Duration,
+ state: Mutex<BurstState>,
+}
+
+#[derive(Debug)]
+struct BurstState {
+ /// within の期間内の条件を満たしたログの日時と発言者
+ matches: VecDeque<(DateTime<FixedOffset>, String)>,
+ armed: bool,
+ last_match: Option<DateTime<FixedOffset>>,
+}
+
+impl BurstDetector {
+ pub fn new(within: Duration, min_count: usize, distinct_names: bool, quiet: Duration) -> Self {
+  BurstDetector {
+   within,
+   min_count,
+   distinct_names,
+   quiet,
+   state: Mutex::new(BurstState {
+    matches: VecDeque::new(),
+    armed: true,
+    last_match: None,
+   }),
+  }
+ }
+
+ /// 条件を満たしたログを記録し、発動する場合は None を、発動しない場合はその理由の設定項目名を返します
+ pub fn record(&self, name: &str, datetime: &DateTime<FixedOffset>) -> Option<&'static str> {
+  let mut state = self.state.lock().unwrap();
+  let quiet = state
+   .last_match
+   .is_some_and(|last_match| *datetime - last_match >= self.quiet);
+  state.last_match = Some(*datetime);
+  if !state.armed {
+   if !quiet {
+    return Some("quiet");
+   }
+   state.armed = true;
+   state.matches.clear();
+  }
+
+  state.matches.push_back((*datetime, name.to_string()));
+  while state
+   .matches
+   .front()
+   .is_some_and(|(matched, _)| *datetime - *matched >= self.within)
+  {
+   state.matches.pop_front();
+  }
+  let count = match self.distinct_names {
+   true => state
+    .matches
+    .iter()
+    .map(|(_, name)| name)
+    .collect::<HashSet<_>>()
+    .len(),
+   false => state.matches.len(),
+  };
+  if count < self.min_count {
+   return Some("min_count");
+  }
+  state.armed = false;
+  state.matches.clear();
+  None
+ }
+}
+
+/// sequence の条件を順番に満たしたログを追跡し、最後の条件を満たしたときに発動します
+///
+/// within が設定されている場合は、最初の条件を満たしてから within の期間内に
+/// 最後の条件まで満たす必要があります。
+#[derive(Debug)]
+pub struct SequenceTracker<'a> {
+ steps: Vec<Filter<'a>>,
+ within: Option<Duration>,
+ /// 次に満たす条件の番号と、最初の条件を満たしたログの日時
+ state: Mutex<(usize, Option<DateTime<FixedOffset>>)>,
+}
+
+impl<'a> SequenceTracker<'a> {
+ pub fn new(steps: Vec<Filter<'a>>, within: Option<Duration>) -> Self {
+  SequenceTracker {
+   steps,
+   within,
+   state: Mutex::new((0, None)),
+  }
+ }
+
+ /// 最後の条件を満たして発動する場合は最初の条件を満たしてからの経過時間を返します
+ pub fn record(&self, ngs_log: &NgsLog) -> Option<Duration> {
+  let mut state = self.state.lock().unwrap();
+  let datetime = *ngs_log.get_datetime();
+  if let (Some(within), Some(started)) = (self.within, state.1) {
+   if datetime - started > within {
+    *state = (0, None);
+   }
+  }
+  let (step, started) = *state;
+  if step > 0 && self.steps[step].matches(ngs_log) {
+   if step + 1 < self.steps.len() {
+    state.0 += 1;
+    return None;
+   }
+   *state = (0, None);
+   return started.map(|started| datetime - started);
+  }
+  // 途中の条件を満たさなくても、最初の条件を満たしたらそこから数え直します
+  if self.steps.first()?.matches(ngs_log) {
+   if self.steps.len() == 1 {
+    return Some(Duration::zero());
+   }
+   *state = (1, Some(datetime));
+  }
+  None
+ }
+}
+
+#[cfg(test)]
+mod tests {
+ use super::*;
+ use crate::conf::Conf;
+ use crate::ngs_log::{chat_log, NgsLogChannel};
+ use chrono::TimeZone;
+
+ fn at(minute: u32, second: u32) -> DateTime<FixedOffset> {
+  FixedOffset::east_opt(9 * 3600)
+   .unwrap()
+   .with_ymd_and_hms(2021, 8, 19, 20, minute, second)
+   .unwrap()
+ }
+
+ #[test]
+ fn burst_fires_once_for_distinct_names_and_rearms_after_quiet_period() {
+  let burst = BurstDetector::new(Duration::minutes(2), 3, true, Duration::minutes(1));
+  assert_eq!(burst.record("A", &at(0, 0)), Some("min_count"));
+  assert_eq!(burst.record("A", &at(0, 10)), Some("min_count"));
+  assert_eq!(burst.record("B", &at(0, 20)), Some("min_count"));
+  // A の 0:00 は2分の期間から外れますが、 0:10 の A が残っています
+  assert_eq!(burst.record("C", &at(2, 5)), None);
+  assert_eq!(burst.record("D", &at(2, 30)), Some("quiet"));
+  assert_eq!(burst.record("E", &at(3, 29)), Some("quiet"));
+  // 1分間途切れたので再び数え始めます
+  assert_eq!(burst.record("A", &at(4, 30)), Some("min_count"));
+ }
+
+ #[test]
+ fn burst_counts_every_log_without_distinct_names() {
+  let burst = BurstDetector::new(Duration::seconds(30), 2, false, Duration::seconds(30));
+  assert_eq!(burst.record("A", &at(0, 0)), Some("min_count"));
+  assert_eq!(burst.record("A", &at(0, 40)), Some("min_count"));
+  assert_eq!(burst.record("A", &at(0, 50)), None);
+ }
+
+ #[test]
+ fn sequence_fires_on_last_step_within_the_window() {
+  let conf: Conf = toml::from_str(
+   r#"
+[[if]]
+sequence = [{keywords = ["/la console2"]}, {keywords = ["/la console2"]}]
+"#,
+  )
+  .unwrap();
+  let steps = conf.r#if.as_ref().unwrap()[0]
+   .sequence
+   .as_ref()
+   .unwrap()
+   .iter()
+   .map(|step| Filter::compile(0, "sequence", step).unwrap())
+   .collect();
+  let sequence = SequenceTracker::new(steps, Some(Duration::minutes(15)));
+  let logged_at = |datetime, body| {
+   let mut ngs_log = chat_log(NgsLogChannel::Party, "L,A.M.", body);
+   *ngs_log.get_datetime_mut() = datetime;
+   ngs_log
+  };
+  assert_eq!(sequence.record(&logged_at(at(0, 0), "/la console2")), None);
+  assert_eq!(sequence.record(&logged_at(at(5, 0), "hello")), None);
+  assert_eq!(
+   sequence.record(&logged_at(at(12, 34), "/la console2")),
+   Some(Duration::seconds(754))
+  );
+  assert_eq!(sequence.record(&logged_at(at(20, 0), "/la console2")), None);
+  // 15分を過ぎたので 20:00 のログから数え直します
+  assert_eq!(sequence.record(&logged_at(at(36, 0), "/la console2")), None);
+  assert_eq!(
+   sequence.record(&logged_at(at(40, 0), "/la console2")),
+   Some(Duration::minutes(4))
+  );
+ }
+}